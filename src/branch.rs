@@ -0,0 +1,92 @@
+use crate::line_file::LineFile;
+use crate::logger::Logger;
+use crate::server_config::ServerConfig;
+
+/// The branch whose pushes get deployed, set with `branch:set` and persisted in `<data_root>/<app>/BRANCH`.
+/// It takes precedence over `deploy_branch` in ruku.yml, and unlike that it can be set before the branch
+/// has ever been deployed.
+pub struct DeployBranch<'a> {
+    file: LineFile<'a>,
+    branch: Option<String>,
+}
+
+impl<'a> DeployBranch<'a> {
+    pub fn load(log: &'a Logger, server_config: &ServerConfig, app: &str) -> DeployBranch<'a> {
+        let file = LineFile::new(log, server_config, app, "BRANCH", "deploy branch");
+        let branch = file.read().first().map(|b| b.trim().to_string());
+
+        DeployBranch { file, branch }
+    }
+
+    pub fn get(&self) -> Option<&str> {
+        self.branch.as_deref()
+    }
+
+    pub fn set(&mut self, branch: Option<&str>) {
+        self.branch = branch.map(String::from);
+    }
+
+    pub fn save(&self) {
+        self.file.write(self.branch.clone());
+    }
+}
+
+/// A name git accepts for a branch, following `git check-ref-format --branch`.
+pub fn is_valid_branch(branch: &str) -> bool {
+    !branch.is_empty()
+        && !branch.starts_with('-')
+        && !branch.starts_with('/')
+        && !branch.ends_with('/')
+        && !branch.ends_with('.')
+        && !branch.contains("..")
+        && !branch.contains("//")
+        && !branch.contains("@{")
+        && branch != "@"
+        && branch
+            .split('/')
+            .all(|component| !component.starts_with('.') && !component.ends_with(".lock"))
+        && branch
+            .chars()
+            .all(|c| !c.is_ascii_control() && !c.is_whitespace() && !"~^:?*[\\".contains(c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_valid_branch_accepts_git_branch_names() {
+        assert!(is_valid_branch("main"));
+        assert!(is_valid_branch("production"));
+        assert!(is_valid_branch("release/2024.06"));
+        assert!(is_valid_branch("feature_x-1"));
+    }
+
+    #[test]
+    fn is_valid_branch_rejects_what_git_rejects() {
+        for branch in [
+            "",
+            "-main",
+            "/main",
+            "main/",
+            "main.",
+            "a..b",
+            "a//b",
+            "a@{1}",
+            "@",
+            ".hidden",
+            "a/.hidden",
+            "main.lock",
+            "my branch",
+            "a~1",
+            "a^",
+            "a:b",
+            "a?",
+            "a*",
+            "a[b",
+            "a\\b",
+        ] {
+            assert!(!is_valid_branch(branch), "{}", branch);
+        }
+    }
+}
@@ -1,76 +1,614 @@
 use std::collections::HashMap;
+use std::io::Write;
 use std::str::FromStr;
+use std::time::{Duration, Instant};
 
-use bollard::container::{CreateContainerOptions, ListContainersOptions, StartContainerOptions};
+use bollard::container::{
+    AttachContainerOptions, AttachContainerResults, CreateContainerOptions, ListContainersOptions, LogOutput,
+    LogsOptions, NetworkingConfig, RenameContainerOptions, ResizeContainerTtyOptions, StartContainerOptions,
+    WaitContainerOptions,
+};
+use bollard::errors::Error::{self, DockerContainerWaitError};
 use bollard::models::{
-    ContainerCreateResponse, ContainerStateStatusEnum, ContainerSummary, HostConfig, PortBinding, PortMap,
+    ContainerCreateResponse, ContainerInspectResponse, ContainerStateStatusEnum, ContainerSummary,
+    ContainerWaitResponse, EndpointSettings, HealthConfig, HealthStatusEnum, HostConfig,
 };
 use bollard::Docker;
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout};
 
+use crate::environment::Environment;
 use crate::logger::Logger;
-use crate::misc::get_image_name_with_version;
-use crate::model::RukuConfig;
+use crate::managed::find_container;
+use crate::model::{HealthCheck, Resources, RukuConfig};
+use crate::network::{app_network, Network};
+use crate::process::{processes, Process, WEB};
+use crate::scale::Scale;
+use crate::terminal::{self, RawMode};
+
+const HEALTH_CHECK_RETRIES: u32 = 30;
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// How long a process without a port must stay up before it counts as started
+const STARTUP_GRACE_PERIOD: Duration = Duration::from_secs(5);
+/// Labels telling which app and process a container runs, the app one also marks the app's images
+pub const APP_LABEL: &str = "ruku.app";
+const PROCESS_LABEL: &str = "ruku.process";
+const REPLICA_LABEL: &str = "ruku.replica";
+const CANDIDATE_SUFFIX: &str = "-next";
+/// Label of containers that run a single command, e.g. a cron job, rather than a process
+const ONE_OFF_LABEL: &str = "ruku.one_off";
+
+/// What `ruku apps` shows about an app's container.
+pub struct ContainerStatus {
+    pub state: String,
+    pub image: Option<String>,
+    pub started_at: Option<DateTime<Utc>>,
+}
 
 pub struct Container<'a> {
     log: &'a Logger,
     name: &'a str,
     docker: &'a Docker,
     config: &'a RukuConfig,
+    env: &'a Environment<'a>,
+    scale: &'a Scale<'a>,
 }
 
 impl<'a> Container<'a> {
-    pub fn new(log: &'a Logger, name: &'a str, docker: &'a Docker, config: &'a RukuConfig) -> Container<'a> {
+    pub fn new(
+        log: &'a Logger,
+        name: &'a str,
+        docker: &'a Docker,
+        config: &'a RukuConfig,
+        env: &'a Environment<'a>,
+        scale: &'a Scale<'a>,
+    ) -> Container<'a> {
         Container {
             log,
             name,
             docker,
             config,
+            env,
+            scale,
         }
     }
 
-    pub async fn run(&self) {
-        let image_name_with_version = get_image_name_with_version(self.name, &self.config.version);
+    /// Replace the app's containers with ones running `image`, one per process type.
+    pub async fn run(&self, image: &str) {
+        let network = app_network(self.name);
+        Network::new(self.log, self.docker, &network).ensure().await;
 
-        if let Some(container) = self.get().await {
-            let container_id = container.id.as_deref().unwrap_or_else(|| {
-                self.log.error("Failed to get container id");
+        let processes = processes(self.config);
+        let replicas: Vec<(&Process, usize)> = processes
+            .iter()
+            .flat_map(|process| (1..=self.scale.get(&process.name)).map(move |replica| (process, replica)))
+            .collect();
+
+        let (stale, mut current): (Vec<_>, Vec<_>) = self.list().await.into_iter().partition(is_candidate);
+        for container in stale {
+            // A deploy interrupted between removing the old containers and renaming the new ones leaves
+            // candidates that are the only ones serving, so finish that swap rather than dropping them
+            let (process, replica) = (process_of(&container), replica_of(&container));
+            let taken = current
+                .iter()
+                .any(|c| process_of(c) == process && replica_of(c) == replica);
+            match container.id.as_deref() {
+                Some(id) if !taken && container.state.as_deref() == Some("running") => {
+                    self.rename(id, &self.container_name(process, replica)).await;
+                    current.push(container);
+                }
+                _ => self.discard(&container).await,
+            }
+        }
+
+        // Bring every replica up next to the old containers before touching the old ones, so a broken
+        // release leaves them all running
+        let mut candidates: Vec<(&Process, usize, String)> = vec![];
+        for (process, replica) in replicas {
+            let name = self.candidate_name(&process.name, replica);
+            let Some(candidate_id) = self.launch(process, replica, &name, image).await else {
+                for (_, _, candidate_id) in &candidates {
+                    self.stop_and_remove(candidate_id).await;
+                }
+                self.log.error(&format!(
+                    "New {}.{} container never became healthy, the previous containers are still running",
+                    process.name, replica
+                ));
                 std::process::exit(1);
-            });
-            let container_state = container.state.as_deref().unwrap_or_else(|| {
-                self.log.error("Failed to get container state");
+            };
+            candidates.push((process, replica, candidate_id));
+        }
+        self.log.step("New containers are healthy, switching traffic");
+
+        // The proxy and the host port forwarder reach all web containers under the app name, so the old
+        // ones can simply go away. This also drops the containers of process types the app no longer
+        // has, or scaled down ones.
+        for container in &current {
+            self.discard(container).await;
+        }
+        for (process, replica, candidate_id) in candidates {
+            self.rename(&candidate_id, &self.container_name(&process.name, replica))
+                .await;
+        }
+    }
+
+    /// Stop and remove the containers of every process.
+    pub async fn end(&self) {
+        let containers = self.list().await;
+        if containers.is_empty() {
+            self.log.error("No application is running");
+        }
+        for container in &containers {
+            self.discard(container).await;
+        }
+    }
+
+    /// Remove the app's containers, including leftovers from an interrupted deploy and one-off ones.
+    pub async fn destroy(&self) {
+        for container in &self.list_all().await {
+            self.discard(container).await;
+        }
+    }
+
+    /// Name of the container running a replica of a process of the app, counting from 1.
+    fn container_name(&self, process: &str, replica: usize) -> String {
+        format!("{}-{}-{}", self.name, process, replica)
+    }
+
+    /// Name of the container a deploy starts next to the live one before swapping them.
+    fn candidate_name(&self, process: &str, replica: usize) -> String {
+        format!("{}{}", self.container_name(process, replica), CANDIDATE_SUFFIX)
+    }
+
+    pub async fn is_running(&self) -> bool {
+        self.get()
+            .await
+            .and_then(|c| c.state)
+            .is_some_and(|state| state == "running")
+    }
+
+    pub async fn status(&self) -> Option<ContainerStatus> {
+        let container = self.get().await?;
+        let state = container.state.unwrap_or_default();
+
+        let mut started_at = None;
+        if state == "running" {
+            if let Some(id) = container.id.as_deref() {
+                started_at = self
+                    .inspect(id)
+                    .await
+                    .state
+                    .and_then(|s| s.started_at)
+                    .and_then(|t| DateTime::parse_from_rfc3339(&t).ok())
+                    .map(|t| t.with_timezone(&Utc));
+            }
+        }
+
+        Some(ContainerStatus {
+            state,
+            image: container.image,
+            started_at,
+        })
+    }
+
+    pub async fn logs(
+        &self,
+        process: &str,
+        replica: usize,
+        follow: bool,
+        tail: Option<usize>,
+        since: i64,
+        timestamps: bool,
+    ) {
+        let Some(container_id) = self.find_process(process, replica).await.and_then(|c| c.id) else {
+            self.log
+                .error(&format!("No {}.{} process is running", process, replica));
+            std::process::exit(1);
+        };
+
+        let options = LogsOptions {
+            follow,
+            stdout: true,
+            stderr: true,
+            since,
+            timestamps,
+            tail: tail.map_or("all".to_string(), |t| t.to_string()),
+            ..Default::default()
+        };
+
+        let mut stream = self.docker.logs(&container_id, Some(options));
+        while let Some(output) = stream.next().await {
+            let output = output.unwrap_or_else(|e| {
+                self.log.error(&format!("Failed to read container logs: {}", e));
                 std::process::exit(1);
             });
-            match ContainerStateStatusEnum::from_str(container_state).unwrap() {
-                ContainerStateStatusEnum::EMPTY => {}
-                ContainerStateStatusEnum::RUNNING | ContainerStateStatusEnum::RESTARTING => {
-                    self.stop_and_remove(container_id).await;
+            // The reader went away, e.g. `ruku logs app | head`
+            if write_output(output).is_err() {
+                break;
+            }
+        }
+    }
+
+    /// Whether the one-off container `name` is still running, e.g. a cron job that overran its schedule.
+    pub async fn is_running_once(&self, name: &str) -> bool {
+        self.find(name)
+            .await
+            .is_some_and(|c| c.state.as_deref() == Some("running"))
+    }
+
+    /// Run `command` to completion in a one-off container from `image`, with the app's env, network and
+    /// resource limits, handing its output to `on_output` as it comes. Returns the exit code, or nothing
+    /// when the container could not be run, which has been reported already.
+    pub async fn run_once(
+        &self,
+        name: &str,
+        image: &str,
+        command: &str,
+        mut on_output: impl FnMut(LogOutput),
+    ) -> Option<i64> {
+        if let Some(container) = self.find(name).await {
+            if container.state.as_deref() == Some("running") {
+                self.log.error(&format!("{} is still running", name));
+                return None;
+            }
+            self.discard(&container).await;
+        }
+
+        let config = bollard::container::Config {
+            cmd: Some(vec!["bash".to_string(), "-c".to_string(), command.to_string()]),
+            ..self.one_off_config(image)
+        };
+        let container_id = self.create_one_off(name, config).await?;
+
+        let mut exit_code = None;
+        if self.start(&container_id).await {
+            let options = LogsOptions::<String> {
+                follow: true,
+                stdout: true,
+                stderr: true,
+                ..Default::default()
+            };
+            let mut stream = self.docker.logs(&container_id, Some(options));
+            while let Some(Ok(output)) = stream.next().await {
+                on_output(output);
+            }
+            exit_code = self.wait_for_exit(&container_id).await;
+        }
+
+        if let Some(container) = self.find(name).await {
+            self.discard(&container).await;
+        }
+        exit_code
+    }
+
+    /// Run `command` in a one-off container from `image` with ruku's stdin and stdout attached to it,
+    /// allocating a TTY when `tty` is set. Returns the exit code, or nothing when the container could
+    /// not be run, which has been reported already. Docker removes the container once it exits, so it
+    /// doesn't outlive ruku being killed.
+    pub async fn run_attached(&self, name: &str, image: &str, command: &[String], tty: bool) -> Option<i64> {
+        let mut config = self.one_off_config(image);
+        if let Some(host_config) = config.host_config.as_mut() {
+            host_config.auto_remove = Some(true);
+        }
+        let config = bollard::container::Config {
+            cmd: Some(command.to_vec()),
+            tty: Some(tty),
+            open_stdin: Some(true),
+            // Closing our stdin ends the command's input, e.g. `ruku run app -- psql < dump.sql`
+            stdin_once: Some(true),
+            attach_stdin: Some(true),
+            attach_stdout: Some(true),
+            attach_stderr: Some(true),
+            ..config
+        };
+        let container_id = self.create_one_off(name, config).await?;
+
+        // Attach before starting so no early output is lost
+        let options = AttachContainerOptions::<String> {
+            stdin: Some(true),
+            stdout: Some(true),
+            stderr: Some(true),
+            stream: Some(true),
+            ..Default::default()
+        };
+        let attached = self.docker.attach_container(&container_id, Some(options)).await;
+        let mut exit = self.wait_for_next_exit(&container_id);
+        let mut exit_code = None;
+        let mut started = false;
+        match attached {
+            Ok(AttachContainerResults { mut output, mut input }) if self.start(&container_id).await => {
+                started = true;
+                let raw_mode = if tty { RawMode::enable() } else { None };
+                if let Some((height, width)) = terminal::size().filter(|_| tty) {
+                    let options = ResizeContainerTtyOptions { height, width };
+                    let _ = self.docker.resize_container_tty(&container_id, options).await;
                 }
-                ContainerStateStatusEnum::REMOVING => {}
-                ContainerStateStatusEnum::CREATED
-                | ContainerStateStatusEnum::PAUSED
-                | ContainerStateStatusEnum::EXITED
-                | ContainerStateStatusEnum::DEAD => {
-                    self.remove(container_id).await;
+
+                let stdin = tokio::spawn(async move {
+                    let _ = tokio::io::copy(&mut tokio::io::stdin(), &mut input).await;
+                    let _ = input.shutdown().await;
+                });
+                while let Some(Ok(chunk)) = output.next().await {
+                    if write_output(chunk).is_err() {
+                        break;
+                    }
                 }
+                stdin.abort();
+                drop(raw_mode);
+                exit_code = self.exit_code((&mut exit).await.ok().flatten());
             }
-            let new_container = self.create(image_name_with_version).await;
-            self.start(&new_container.id).await;
-        } else {
-            let container = self.create(image_name_with_version).await;
-            self.start(&container.id).await;
+            Ok(_) => {}
+            Err(e) => self.log.error(&format!("Failed to attach to container: {}", e)),
         }
+        exit.abort();
+
+        // Only a container that ran gets removed by Docker
+        if !started {
+            if let Some(container) = self.find(name).await {
+                self.discard(&container).await;
+            }
+        }
+        exit_code
     }
 
-    pub async fn end(&self) {
-        if let Some(container) = self.get().await {
-            let container_id = container.id.as_deref().unwrap_or_else(|| {
-                self.log.error("Failed to get container id");
+    /// Settings of a one-off container from `image`, to be completed with the command to run.
+    fn one_off_config(&self, image: &str) -> bollard::container::Config<String> {
+        bollard::container::Config {
+            image: Some(image.to_string()),
+            env: Some(self.docker_env()),
+            host_config: Some(self.host_config()),
+            labels: Some(HashMap::from([
+                (APP_LABEL.to_string(), self.name.to_string()),
+                (ONE_OFF_LABEL.to_string(), "true".to_string()),
+            ])),
+            ..Default::default()
+        }
+    }
+
+    async fn create_one_off(&self, name: &str, config: bollard::container::Config<String>) -> Option<String> {
+        // The release command of a first deploy runs before any process container created the network,
+        // and it may have been removed since
+        Network::new(self.log, self.docker, &app_network(self.name))
+            .ensure()
+            .await;
+
+        match self
+            .docker
+            .create_container(Some(CreateContainerOptions { name, platform: None }), config)
+            .await
+        {
+            Ok(container) => Some(container.id),
+            Err(e) => {
+                self.log.error(&format!("Failed to create container: {}", e));
+                None
+            }
+        }
+    }
+
+    /// Wait for a container to exit and return its exit code.
+    async fn wait_for_exit(&self, container_id: &str) -> Option<i64> {
+        let mut wait = self
+            .docker
+            .wait_container(container_id, None::<WaitContainerOptions<String>>);
+        self.exit_code(wait.next().await)
+    }
+
+    /// Start waiting for the next exit of a container that is yet to be started. A container Docker
+    /// removes on exit is gone by the time a wait for it could begin afterwards.
+    fn wait_for_next_exit(&self, container_id: &str) -> JoinHandle<Option<Result<ContainerWaitResponse, Error>>> {
+        let docker = self.docker.clone();
+        let container_id = container_id.to_string();
+        tokio::spawn(async move {
+            let options = WaitContainerOptions { condition: "next-exit" };
+            docker.wait_container(&container_id, Some(options)).next().await
+        })
+    }
+
+    /// The exit code from the outcome of waiting for a container.
+    fn exit_code(&self, wait: Option<Result<ContainerWaitResponse, Error>>) -> Option<i64> {
+        // A non-zero exit code comes back as an error
+        match wait {
+            Some(Ok(response)) => Some(response.status_code),
+            Some(Err(DockerContainerWaitError { code, .. })) => Some(code),
+            Some(Err(e)) => {
+                self.log.error(&format!("Failed to wait for container: {}", e));
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Environment of every container of the app: its config vars, with `PORT` set to the port the app
+    /// is routed to so Procfile commands can bind to `$PORT`.
+    fn docker_env(&self) -> Vec<String> {
+        let mut env = self.env.to_docker_env();
+        env.retain(|var| !var.starts_with("PORT="));
+        env.push(format!("PORT={}", self.config.port));
+        env
+    }
+
+    /// Host settings every container of the app shares.
+    fn host_config(&self) -> HostConfig {
+        let mut host_config = HostConfig {
+            network_mode: Some(app_network(self.name)),
+            ..Default::default()
+        };
+        if let Some(resources) = &self.config.resources {
+            host_config.memory = resources.memory_bytes();
+            host_config.cpu_shares = resources.cpu_shares;
+            host_config.pids_limit = resources.pids;
+            if let Some(cpu_quota) = resources.cpu_quota() {
+                host_config.cpu_period = Some(Resources::CPU_PERIOD);
+                host_config.cpu_quota = Some(cpu_quota);
+            }
+        }
+        host_config
+    }
+
+    /// Stop (if needed) and remove a container regardless of the state it is in.
+    async fn discard(&self, container: &ContainerSummary) {
+        let container_id = container.id.as_deref().unwrap_or_else(|| {
+            self.log.error("Failed to get container id");
+            std::process::exit(1);
+        });
+        let container_state = container.state.as_deref().unwrap_or_else(|| {
+            self.log.error("Failed to get container state");
+            std::process::exit(1);
+        });
+        match ContainerStateStatusEnum::from_str(container_state).unwrap() {
+            ContainerStateStatusEnum::EMPTY => {}
+            ContainerStateStatusEnum::RUNNING | ContainerStateStatusEnum::RESTARTING => {
+                self.stop_and_remove(container_id).await;
+            }
+            ContainerStateStatusEnum::REMOVING => {}
+            ContainerStateStatusEnum::CREATED
+            | ContainerStateStatusEnum::PAUSED
+            | ContainerStateStatusEnum::EXITED
+            | ContainerStateStatusEnum::DEAD => {
+                self.remove(container_id).await;
+            }
+        }
+    }
+
+    /// Wait for the new container to become healthy, using the `ruku.yml` health check when there is one.
+    /// Processes other than web don't listen on the app port, so they only have to stay up for a moment.
+    async fn wait_until_healthy(&self, process: &Process, container_id: &str) -> bool {
+        if !process.is_web() {
+            return self.wait_for_startup(container_id).await;
+        }
+        match &self.config.healthcheck {
+            Some(healthcheck) => self.wait_for_health_check(container_id, healthcheck).await,
+            None => self.wait_for_port(container_id).await,
+        }
+    }
+
+    async fn wait_for_startup(&self, container_id: &str) -> bool {
+        sleep(STARTUP_GRACE_PERIOD).await;
+        if !self
+            .inspect(container_id)
+            .await
+            .state
+            .and_then(|s| s.running)
+            .unwrap_or(false)
+        {
+            self.log.error("New container exited during startup");
+            return false;
+        }
+        true
+    }
+
+    /// Follow the status of the Docker health check until it settles on healthy or unhealthy.
+    async fn wait_for_health_check(&self, container_id: &str, healthcheck: &HealthCheck) -> bool {
+        let deadline = Instant::now()
+            + Duration::from_secs(
+                healthcheck.start_period
+                    + (healthcheck.interval + healthcheck.timeout) * (healthcheck.retries as u64 + 1),
+            );
+        while Instant::now() < deadline {
+            let container = self.inspect(container_id).await;
+            let state = container.state.unwrap_or_default();
+            if !state.running.unwrap_or(false) {
+                self.log.error("New container exited during startup");
+                return false;
+            }
+
+            let health = state.health.unwrap_or_default();
+            match health.status {
+                Some(HealthStatusEnum::HEALTHY) => return true,
+                Some(HealthStatusEnum::UNHEALTHY) => {
+                    let output = health
+                        .log
+                        .and_then(|log| log.into_iter().last())
+                        .and_then(|result| result.output)
+                        .unwrap_or_default();
+                    self.log.error(&format!(
+                        "Health check failed {} times: {}",
+                        healthcheck.retries,
+                        describe_health_check(healthcheck, self.config.port)
+                    ));
+                    if !output.trim().is_empty() {
+                        self.log.error(output.trim());
+                    }
+                    return false;
+                }
+                _ => sleep(HEALTH_CHECK_INTERVAL).await,
+            }
+        }
+        self.log.error(&format!(
+            "Timed out waiting for health check: {}",
+            describe_health_check(healthcheck, self.config.port)
+        ));
+        false
+    }
+
+    /// Wait for the app inside the container to accept connections on its port. The container's own
+    /// address is probed rather than the host port, since docker-proxy accepts connections on the
+    /// host port even when nothing listens behind it.
+    async fn wait_for_port(&self, container_id: &str) -> bool {
+        for _ in 0..HEALTH_CHECK_RETRIES {
+            let container = self.inspect(container_id).await;
+            if !container.state.and_then(|s| s.running).unwrap_or(false) {
+                self.log.error("New container exited during startup");
+                return false;
+            }
+
+            let ip = container
+                .network_settings
+                .and_then(|n| n.networks)
+                .and_then(|networks| {
+                    networks
+                        .into_values()
+                        .find_map(|n| n.ip_address.filter(|ip| !ip.is_empty()))
+                });
+            if let Some(ip) = ip {
+                let addr = format!("{}:{}", ip, self.config.port);
+                if let Ok(Ok(_)) = timeout(HEALTH_CHECK_INTERVAL, TcpStream::connect(&addr)).await {
+                    return true;
+                }
+            }
+            sleep(HEALTH_CHECK_INTERVAL).await;
+        }
+        self.log
+            .error(&format!("App never accepted connections on port {}", self.config.port));
+        false
+    }
+
+    async fn inspect(&self, container_id: &str) -> ContainerInspectResponse {
+        self.docker
+            .inspect_container(container_id, None)
+            .await
+            .unwrap_or_else(|_| {
+                self.log.error("Failed to inspect container");
                 std::process::exit(1);
-            });
-            self.stop_and_remove(container_id).await;
-        } else {
-            self.log.error("No application is running");
+            })
+    }
+
+    /// Create and start a container and wait for it to become healthy. Failures are reported rather
+    /// than exiting, and the broken container is cleaned up, so the caller can decide how to recover.
+    async fn launch(&self, process: &Process, replica: usize, name: &str, image_name: &str) -> Option<String> {
+        let container = self.create(process, replica, name, image_name).await?;
+        if self.start(&container.id).await && self.wait_until_healthy(process, &container.id).await {
+            return Some(container.id);
         }
+
+        if let Some(container) = self.find(name).await {
+            self.discard(&container).await;
+        }
+        None
+    }
+
+    async fn rename(&self, container_id: &str, name: &str) {
+        self.docker
+            .rename_container(container_id, RenameContainerOptions { name })
+            .await
+            .unwrap_or_else(|e| {
+                self.log.error(&format!("Failed to rename container: {}", e));
+                std::process::exit(1);
+            });
     }
 
     async fn stop_and_remove(&self, container_id: &str) {
@@ -100,72 +638,226 @@ impl<'a> Container<'a> {
         self.log.step(&format!("Removed container with id: {}", container_id));
     }
 
-    async fn start(&self, container_id: &str) {
-        self.docker
+    async fn start(&self, container_id: &str) -> bool {
+        if let Err(e) = self
+            .docker
             .start_container(container_id, None::<StartContainerOptions<String>>)
             .await
-            .unwrap_or_else(|_| {
-                self.log.error("Failed to start container");
-                std::process::exit(1);
-            });
+        {
+            self.log.error(&format!("Failed to start container: {}", e));
+            return false;
+        }
         self.log.step(&format!("Started container with id: {}", container_id));
+        true
     }
 
+    /// The container that stands for the app as a whole: the web process if it has one.
     pub async fn get(&self) -> Option<ContainerSummary> {
+        primary(&self.list().await).cloned()
+    }
+
+    async fn find_process(&self, process: &str, replica: usize) -> Option<ContainerSummary> {
+        self.list()
+            .await
+            .into_iter()
+            .find(|c| !is_candidate(c) && process_of(c) == process && replica_of(c) == replica)
+    }
+
+    /// The containers running the app's processes, whatever their state. One-off containers are left
+    /// to finish on their own.
+    async fn list(&self) -> Vec<ContainerSummary> {
+        self.list_all().await.into_iter().filter(|c| !is_one_off(c)).collect()
+    }
+
+    async fn list_all(&self) -> Vec<ContainerSummary> {
+        let label = format!("{}={}", APP_LABEL, self.name);
         let mut filters = HashMap::new();
-        filters.insert("name", vec![self.name]);
+        filters.insert("label", vec![label.as_str()]);
 
         let options = Some(ListContainersOptions {
             all: true,
             filters,
-            limit: Some(1),
             ..Default::default()
         });
-        let containers = self.docker.list_containers(options).await.unwrap_or_else(|_| {
+        let mut containers = self.docker.list_containers(options).await.unwrap_or_else(|_| {
             self.log.error("Failed to list containers");
             std::process::exit(1);
         });
-        containers.into_iter().next()
+
+        // Containers from before process types were named after the app and carry no labels
+        if let Some(legacy) = self.find(self.name).await.filter(|c| app_of(c).is_none()) {
+            containers.push(legacy);
+        }
+        containers
     }
 
-    pub async fn create(&self, image_name: String) -> ContainerCreateResponse {
-        let create_options = CreateContainerOptions {
-            name: self.name,
-            platform: None,
-        };
+    async fn find(&self, name: &str) -> Option<ContainerSummary> {
+        find_container(self.log, self.docker, name).await
+    }
+
+    /// Create a container for a process on the app's network. Only the web process gets the app port.
+    async fn create(
+        &self,
+        process: &Process,
+        replica: usize,
+        name: &str,
+        image_name: &str,
+    ) -> Option<ContainerCreateResponse> {
+        let create_options = CreateContainerOptions { name, platform: None };
 
         let exposed_port = format!("{}/tcp", self.config.port);
-        let mut host_config = HostConfig::default();
-        let mut port_bindings = PortMap::new();
-        port_bindings.insert(
-            exposed_port.clone(),
-            Some(vec![PortBinding {
-                host_ip: None,
-                host_port: Some(self.config.port.to_string()),
-            }]),
+        let mut host_config = self.host_config();
+        host_config.restart_policy = Some(self.config.restart_policy());
+        let network = app_network(self.name);
+
+        // Every web container of the app answers to the app name on the network, which is what the proxy
+        // routes to, so a candidate can take traffic before the old container goes away
+        let mut endpoints_config = HashMap::new();
+        endpoints_config.insert(
+            network,
+            EndpointSettings {
+                aliases: process.is_web().then(|| vec![self.name.to_string()]),
+                ..Default::default()
+            },
         );
-        host_config.port_bindings = Some(port_bindings);
 
         let mut exposed_ports_map: HashMap<String, HashMap<(), ()>> = HashMap::new();
-        exposed_ports_map.insert(exposed_port, HashMap::new());
+        if process.is_web() {
+            exposed_ports_map.insert(exposed_port, HashMap::new());
+        }
+
+        let healthcheck = self
+            .config
+            .healthcheck
+            .as_ref()
+            .filter(|_| process.is_web())
+            .map(|healthcheck| HealthConfig {
+                test: Some(health_check_command(healthcheck, self.config.port)),
+                interval: Some(seconds_to_nanos(healthcheck.interval)),
+                timeout: Some(seconds_to_nanos(healthcheck.timeout)),
+                retries: Some(healthcheck.retries as i64),
+                start_period: Some(seconds_to_nanos(healthcheck.start_period)),
+                ..Default::default()
+            });
 
         let create_container_config = bollard::container::Config {
-            image: Some(image_name),
+            image: Some(image_name.to_string()),
+            cmd: process
+                .command
+                .as_ref()
+                .map(|command| vec!["bash".to_string(), "-c".to_string(), command.clone()]),
+            env: Some(self.docker_env()),
+            labels: Some(HashMap::from([
+                (APP_LABEL.to_string(), self.name.to_string()),
+                (PROCESS_LABEL.to_string(), process.name.clone()),
+                (REPLICA_LABEL.to_string(), replica.to_string()),
+            ])),
             host_config: Some(host_config),
             exposed_ports: Some(exposed_ports_map),
+            healthcheck,
+            networking_config: Some(NetworkingConfig { endpoints_config }),
             ..Default::default()
         };
 
         // Create the container
-        let container = self
+        match self
             .docker
             .create_container(Some(create_options), create_container_config)
             .await
-            .unwrap_or_else(|_| {
-                self.log.error("Failed to create container");
-                std::process::exit(1);
-            });
-        self.log.step(&format!("Created container with id: {}", container.id));
-        container
+        {
+            Ok(container) => {
+                self.log.step(&format!("Created container with id: {}", container.id));
+                Some(container)
+            }
+            Err(e) => {
+                self.log.error(&format!("Failed to create container: {}", e));
+                None
+            }
+        }
+    }
+}
+
+/// Write container output to our own stdout or stderr, keeping the split so callers can redirect them
+/// separately.
+fn write_output(output: LogOutput) -> std::io::Result<()> {
+    match output {
+        LogOutput::StdErr { message } => std::io::stderr().write_all(&message),
+        LogOutput::StdOut { message } | LogOutput::Console { message } => std::io::stdout()
+            .write_all(&message)
+            .and_then(|_| std::io::stdout().flush()),
+        LogOutput::StdIn { .. } => Ok(()),
+    }
+}
+
+/// The web container among `containers` if there is one, otherwise any of them.
+fn primary(containers: &[ContainerSummary]) -> Option<&ContainerSummary> {
+    containers
+        .iter()
+        .find(|c| process_of(c) == WEB)
+        .or_else(|| containers.first())
+}
+
+fn app_of(container: &ContainerSummary) -> Option<&str> {
+    container
+        .labels
+        .as_ref()
+        .and_then(|labels| labels.get(APP_LABEL))
+        .map(String::as_str)
+}
+
+/// Containers from before scaling have no replica label and were the only one of their process.
+fn replica_of(container: &ContainerSummary) -> usize {
+    container
+        .labels
+        .as_ref()
+        .and_then(|labels| labels.get(REPLICA_LABEL))
+        .and_then(|replica| replica.parse().ok())
+        .unwrap_or(1)
+}
+
+fn is_one_off(container: &ContainerSummary) -> bool {
+    container
+        .labels
+        .as_ref()
+        .is_some_and(|labels| labels.contains_key(ONE_OFF_LABEL))
+}
+
+fn is_candidate(container: &ContainerSummary) -> bool {
+    container
+        .names
+        .as_ref()
+        .is_some_and(|names| names.iter().any(|name| name.ends_with(CANDIDATE_SUFFIX)))
+}
+
+/// Containers from before process types have no labels and only ever ran the web process.
+fn process_of(container: &ContainerSummary) -> &str {
+    container
+        .labels
+        .as_ref()
+        .and_then(|labels| labels.get(PROCESS_LABEL))
+        .map_or(WEB, String::as_str)
+}
+
+/// Build the Docker health check test. Nixpacks images don't ship curl, so the probe only relies on
+/// bash's `/dev/tcp`.
+fn health_check_command(healthcheck: &HealthCheck, port: u16) -> Vec<String> {
+    let script = match &healthcheck.path {
+        Some(path) => format!(
+            r#"exec 3<>/dev/tcp/127.0.0.1/{} && printf "GET %s HTTP/1.0\r\nHost: localhost\r\n\r\n" "{}" >&3 && head -n 1 <&3 | grep -q " {} ""#,
+            port, path, healthcheck.status
+        ),
+        None => format!(":> /dev/tcp/127.0.0.1/{}", port),
+    };
+    vec!["CMD".to_string(), "bash".to_string(), "-c".to_string(), script]
+}
+
+fn describe_health_check(healthcheck: &HealthCheck, port: u16) -> String {
+    match &healthcheck.path {
+        Some(path) => format!("GET {} did not return {}", path, healthcheck.status),
+        None => format!("port {} did not accept connections", port),
     }
 }
+
+fn seconds_to_nanos(seconds: u64) -> i64 {
+    Duration::from_secs(seconds).as_nanos() as i64
+}
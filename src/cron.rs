@@ -0,0 +1,276 @@
+use std::fs;
+use std::path::PathBuf;
+
+use chrono::{DateTime, Datelike, TimeZone, Timelike, Utc};
+use cmd_lib::{run_cmd, run_fun};
+use serde::{Deserialize, Serialize};
+
+use crate::logger::Logger;
+use crate::server_config::ServerConfig;
+
+/// How much of a job's output is kept, the end of it being the interesting part
+const MAX_OUTPUT: usize = 64 * 1024;
+
+/// A cron schedule in the classic five field form, `minute hour day-of-month month day-of-week`, or one
+/// of the `@hourly`, `@daily`, `@weekly`, `@monthly` and `@yearly` shorthands. Each field takes `*`,
+/// numbers, ranges and steps, e.g. `*/15`, `1-5` or `0,30`. Times are in the server's local time zone.
+pub struct Schedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl Schedule {
+    pub fn parse(expression: &str) -> Result<Schedule, String> {
+        let expression = match expression.trim() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            expression => expression,
+        };
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(format!("Invalid schedule '{}': expected 5 fields", expression));
+        };
+
+        // Sunday is both 0 and 7
+        let mut weekday_bits = parse_field(weekdays, 0, 7)?;
+        if weekday_bits & (1 << 7) != 0 {
+            weekday_bits = (weekday_bits | 1) & !(1 << 7);
+        }
+        Ok(Schedule {
+            minutes: parse_field(minutes, 0, 59)?,
+            hours: parse_field(hours, 0, 23)?,
+            days: parse_field(days, 1, 31)?,
+            months: parse_field(months, 1, 12)?,
+            weekdays: weekday_bits,
+            any_day: days.starts_with('*'),
+            any_weekday: weekdays.starts_with('*'),
+        })
+    }
+
+    /// Whether the job is due in the minute of `time`, read in the time zone it carries.
+    pub fn matches<Tz: TimeZone>(&self, time: &DateTime<Tz>) -> bool {
+        let day = self.days & (1 << time.day()) != 0;
+        let weekday = self.weekdays & (1 << time.weekday().num_days_from_sunday()) != 0;
+        // Like cron, a job restricted by both day fields runs when either of them matches
+        let day_matches = match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        };
+        self.minutes & (1 << time.minute()) != 0
+            && self.hours & (1 << time.hour()) != 0
+            && self.months & (1 << time.month()) != 0
+            && day_matches
+    }
+}
+
+/// Parse one field of a schedule into a bit set of the values it allows.
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let invalid = || format!("Invalid schedule field '{}'", field);
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|s| *s > 0).ok_or_else(invalid)?),
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            range => match range.split_once('-') {
+                Some((start, end)) => (
+                    start.parse().map_err(|_| invalid())?,
+                    end.parse().map_err(|_| invalid())?,
+                ),
+                // `5/10` means every 10 starting at 5
+                None if part.contains('/') => (range.parse().map_err(|_| invalid())?, max),
+                None => {
+                    let value = range.parse().map_err(|_| invalid())?;
+                    (value, value)
+                }
+            },
+        };
+        if start < min || end > max || start > end {
+            return Err(invalid());
+        }
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+/// The outcome of the last run of a cron job.
+#[derive(Serialize, Deserialize)]
+pub struct CronRun {
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    /// Missing when the job's container could not be started
+    pub exit_code: Option<i64>,
+    pub output: String,
+}
+
+/// The last run of each cron job of an app, persisted in `<data_root>/<app>/cron/<job>.yml`. Jobs run
+/// concurrently, so each one has its own file.
+pub struct CronRuns<'a> {
+    log: &'a Logger,
+    dir: PathBuf,
+}
+
+impl<'a> CronRuns<'a> {
+    pub fn new(log: &'a Logger, server_config: &ServerConfig, app: &str) -> CronRuns<'a> {
+        CronRuns {
+            log,
+            dir: server_config.data_root.join(app).join("cron"),
+        }
+    }
+
+    pub fn last(&self, job: &str) -> Option<CronRun> {
+        let content = fs::read_to_string(self.path(job)).ok()?;
+        serde_yaml::from_str(&content).ok()
+    }
+
+    pub fn record(&self, job: &str, mut run: CronRun) {
+        if run.output.len() > MAX_OUTPUT {
+            let mut start = run.output.len() - MAX_OUTPUT;
+            while !run.output.is_char_boundary(start) {
+                start += 1;
+            }
+            run.output = run.output.split_off(start);
+        }
+
+        fs::create_dir_all(&self.dir).unwrap_or_else(|e| {
+            self.log.error(&format!("Error creating directory: {}", e));
+            std::process::exit(1);
+        });
+        let content = serde_yaml::to_string(&run).unwrap_or_else(|e| {
+            self.log.error(&format!("Error serializing cron run: {}", e));
+            std::process::exit(1);
+        });
+        fs::write(self.path(job), content).unwrap_or_else(|e| {
+            self.log.error(&format!("Error writing cron run: {}", e));
+            std::process::exit(1);
+        });
+    }
+
+    fn path(&self, job: &str) -> PathBuf {
+        self.dir.join(format!("{}.yml", job))
+    }
+}
+
+/// Make sure the user's crontab runs `ruku cron:tick` every minute, which is what starts due jobs.
+pub fn install_crontab(log: &Logger, server_config: &ServerConfig) {
+    // `crontab -l` fails when the user has no crontab yet
+    let current = run_fun!(crontab "-l").unwrap_or_default();
+    if current.lines().any(|line| line.contains("cron:tick")) {
+        return;
+    }
+
+    let entry = format!("* * * * * {} cron:tick", server_config.ruku_binary.display());
+    let content = if current.trim().is_empty() {
+        format!("{}\n", entry)
+    } else {
+        format!("{}\n{}\n", current.trim_end(), entry)
+    };
+    match run_cmd!(echo -n $content | crontab -) {
+        Ok(_) => log.step("Added cron:tick to the crontab"),
+        Err(e) => log.error(&format!(
+            "Failed to update the crontab, add '{}' to it for cron jobs to run: {}",
+            entry, e
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// UTC has no daylight saving switches, so any hour exists exactly once whatever zone the tests run in.
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        // June 2024 starts on a Saturday, so the 2nd is a Sunday
+        Utc.with_ymd_and_hms(2024, 6, day, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn schedule_steps() {
+        let schedule = Schedule::parse("*/15 * * * *").unwrap();
+        for minute in [0, 15, 30, 45] {
+            assert!(schedule.matches(&at(3, 12, minute)));
+        }
+        assert!(!schedule.matches(&at(3, 12, 10)));
+
+        // A step from a starting value runs up to the end of the field
+        let schedule = Schedule::parse("5/20 * * * *").unwrap();
+        assert!(schedule.matches(&at(3, 12, 5)));
+        assert!(schedule.matches(&at(3, 12, 45)));
+        assert!(!schedule.matches(&at(3, 12, 0)));
+    }
+
+    #[test]
+    fn schedule_ranges_and_lists() {
+        let schedule = Schedule::parse("0 9-17/4,20 * * *").unwrap();
+        for hour in [9, 13, 17, 20] {
+            assert!(schedule.matches(&at(3, hour, 0)));
+        }
+        assert!(!schedule.matches(&at(3, 10, 0)));
+        assert!(!schedule.matches(&at(3, 21, 0)));
+        assert!(!schedule.matches(&at(3, 9, 1)));
+    }
+
+    #[test]
+    fn schedule_takes_7_as_sunday() {
+        let schedule = Schedule::parse("0 12 * * 7").unwrap();
+        assert!(schedule.matches(&at(2, 12, 0)));
+        assert!(!schedule.matches(&at(3, 12, 0)));
+        assert!(Schedule::parse("0 12 * * 5-7").unwrap().matches(&at(2, 12, 0)));
+    }
+
+    #[test]
+    fn schedule_matches_either_day_field_when_both_are_restricted() {
+        // The 15th or any Monday
+        let schedule = Schedule::parse("0 12 15 * 1").unwrap();
+        assert!(schedule.matches(&at(15, 12, 0)));
+        assert!(schedule.matches(&at(3, 12, 0)));
+        assert!(!schedule.matches(&at(4, 12, 0)));
+
+        // With one of them left open only the other one counts
+        let schedule = Schedule::parse("0 12 15 * *").unwrap();
+        assert!(schedule.matches(&at(15, 12, 0)));
+        assert!(!schedule.matches(&at(3, 12, 0)));
+        let schedule = Schedule::parse("0 12 */2 * 1").unwrap();
+        assert!(schedule.matches(&at(3, 12, 0)));
+        assert!(!schedule.matches(&at(5, 12, 0)));
+    }
+
+    #[test]
+    fn schedule_shorthands() {
+        let schedule = Schedule::parse("@weekly").unwrap();
+        assert!(schedule.matches(&at(2, 0, 0)));
+        assert!(!schedule.matches(&at(3, 0, 0)));
+        assert!(Schedule::parse("@hourly").unwrap().matches(&at(3, 7, 0)));
+    }
+
+    #[test]
+    fn schedule_rejects_invalid_expressions() {
+        for expression in [
+            "* * * *",
+            "* * * * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * * 13 *",
+            "* * * * 8",
+            "*/0 * * * *",
+            "10-5 * * * *",
+            "a * * * *",
+            "@often",
+        ] {
+            assert!(Schedule::parse(expression).is_err(), "{}", expression);
+        }
+    }
+}
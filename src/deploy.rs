@@ -1,10 +1,12 @@
+use std::io::{self, Write};
+
 use nixpacks::create_docker_image;
 use nixpacks::nixpacks::builder::docker::DockerBuilderOptions;
 use nixpacks::nixpacks::plan::{generator::GeneratePlanOptions, BuildPlan};
 
-use crate::container::Container;
+use crate::container::{Container, APP_LABEL};
 use crate::logger::Logger;
-use crate::misc::get_image_name_with_version;
+use crate::misc::{get_image_name_with_commit, get_image_name_with_version};
 use crate::model::RukuConfig;
 
 pub struct Deploy<'a> {
@@ -13,6 +15,7 @@ pub struct Deploy<'a> {
     path: &'a str,
     config: &'a RukuConfig,
     container: &'a Container<'a>,
+    commit: &'a str,
 }
 
 impl<'a> Deploy<'a> {
@@ -22,6 +25,7 @@ impl<'a> Deploy<'a> {
         path: &'a str,
         config: &'a RukuConfig,
         container: &'a Container<'a>,
+        commit: &'a str,
     ) -> Deploy<'a> {
         Deploy {
             log,
@@ -29,10 +33,12 @@ impl<'a> Deploy<'a> {
             path,
             config,
             container,
+            commit,
         }
     }
 
-    pub async fn run(&self) {
+    /// Build and start the app, returning the image it now runs.
+    pub async fn run(&self) -> String {
         self.log.step(&format!("Running from {}", self.path));
 
         // Nix pack
@@ -43,14 +49,19 @@ impl<'a> Deploy<'a> {
             config_file: None,
         };
 
-        let image_name_with_version = get_image_name_with_version(self.name, &self.config.version);
+        // Tag by commit so every build is kept apart, with the `ruku.yml` version as an extra tag
+        let image = get_image_name_with_commit(self.name, self.commit);
+        let mut tags = vec![image.clone()];
+        tags.extend(get_image_name_with_version(self.name, &self.config.version));
 
+        // Builds run the app's own scripts, so hold them to the same limits as the app
+        let resources = self.config.resources.as_ref();
         let build_options = DockerBuilderOptions {
             name: Some(self.name.to_string()),
             out_dir: None,
             print_dockerfile: false,
-            tags: vec![image_name_with_version.clone()],
-            labels: vec![],
+            tags,
+            labels: vec![format!("{}={}", APP_LABEL, self.name)],
             quiet: false,
             cache_key: None,
             no_cache: false,
@@ -60,8 +71,8 @@ impl<'a> Deploy<'a> {
             current_dir: true,
             no_error_without_start: false,
             incremental_cache_image: None,
-            cpu_quota: None,
-            memory: None,
+            cpu_quota: resources.and_then(|r| r.cpu_quota()).map(|q| q.to_string()),
+            memory: resources.and_then(|r| r.memory_bytes()).map(|m| m.to_string()),
             verbose: false,
             docker_host: None,
             docker_tls_verify: None,
@@ -78,11 +89,42 @@ impl<'a> Deploy<'a> {
                 std::process::exit(1);
             });
 
-        self.log.step(&format!(
-            "Image created successfully with tag {}",
-            image_name_with_version
-        ));
+        self.log.step(&format!("Image created successfully with tag {}", image));
+
+        if let Some(command) = &self.config.release {
+            self.release(&image, command).await;
+        }
+
+        self.container.run(&image).await;
+        image
+    }
+
+    /// Run the release command against the new image while the old containers keep serving, and abort
+    /// the deploy if it fails.
+    async fn release(&self, image: &str, command: &str) {
+        self.log.step(&format!("Running release command: {}", command));
+        let name = format!("{}-release", self.name);
+        let exit_code = self
+            .container
+            .run_once(&name, image, command, |output| {
+                let _ = io::stderr().write_all(&output.into_bytes());
+            })
+            .await;
 
-        self.container.run().await;
+        match exit_code {
+            Some(0) => self.log.step("Release command succeeded"),
+            Some(code) => {
+                self.log.error(&format!(
+                    "Release command failed with exit code {}, the previous release is still running",
+                    code
+                ));
+                std::process::exit(1);
+            }
+            None => {
+                self.log
+                    .error("Release command could not be run, the previous release is still running");
+                std::process::exit(1);
+            }
+        }
     }
 }
@@ -0,0 +1,80 @@
+use std::collections::BTreeSet;
+
+use crate::line_file::LineFile;
+use crate::logger::Logger;
+use crate::server_config::ServerConfig;
+
+/// Domains added to an app with `domains:add`, persisted one per line in `<data_root>/<app>/DOMAINS`.
+/// These come on top of the `domains` listed in the app's ruku.yml.
+pub struct Domains<'a> {
+    file: LineFile<'a>,
+    domains: BTreeSet<String>,
+}
+
+impl<'a> Domains<'a> {
+    pub fn load(log: &'a Logger, server_config: &ServerConfig, app: &str) -> Domains<'a> {
+        let file = LineFile::new(log, server_config, app, "DOMAINS", "domains");
+        let domains = file.read().iter().map(|d| d.trim().to_string()).collect();
+
+        Domains { file, domains }
+    }
+
+    pub fn all(&self) -> &BTreeSet<String> {
+        &self.domains
+    }
+
+    pub fn add(&mut self, domain: &str) -> bool {
+        self.domains.insert(domain.to_lowercase())
+    }
+
+    pub fn remove(&mut self, domain: &str) -> bool {
+        self.domains.remove(&domain.to_lowercase())
+    }
+
+    pub fn save(&self) {
+        self.file.write(self.domains.iter().cloned());
+    }
+}
+
+/// A hostname, optionally with a leading `*.` wildcard label.
+pub fn is_valid_domain(domain: &str) -> bool {
+    let host = domain.strip_prefix("*.").unwrap_or(domain);
+    host.len() <= 253
+        && host.contains('.')
+        && host.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_valid_domain_accepts_hostnames() {
+        assert!(is_valid_domain("example.com"));
+        assert!(is_valid_domain("my-app.example.co.uk"));
+        assert!(is_valid_domain("*.example.com"));
+        assert!(is_valid_domain(&format!("{}.com", "a".repeat(63))));
+    }
+
+    #[test]
+    fn is_valid_domain_rejects_malformed_hostnames() {
+        assert!(!is_valid_domain("localhost"));
+        assert!(!is_valid_domain(""));
+        assert!(!is_valid_domain("example..com"));
+        assert!(!is_valid_domain(".example.com"));
+        assert!(!is_valid_domain("-app.example.com"));
+        assert!(!is_valid_domain("app-.example.com"));
+        assert!(!is_valid_domain("app_1.example.com"));
+        assert!(!is_valid_domain("example.com:8080"));
+        assert!(!is_valid_domain("*.*.example.com"));
+        assert!(!is_valid_domain("a.*.example.com"));
+        assert!(!is_valid_domain(&format!("{}.com", "a".repeat(64))));
+        assert!(!is_valid_domain(&format!("{}.com", "a.".repeat(126))));
+    }
+}
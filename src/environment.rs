@@ -0,0 +1,97 @@
+use std::collections::BTreeMap;
+
+use crate::line_file::LineFile;
+use crate::logger::Logger;
+use crate::server_config::ServerConfig;
+
+/// Per-app environment variables, persisted as `KEY=VALUE` lines in `<data_root>/<app>/ENV`.
+pub struct Environment<'a> {
+    file: LineFile<'a>,
+    vars: BTreeMap<String, String>,
+}
+
+impl<'a> Environment<'a> {
+    pub fn load(log: &'a Logger, server_config: &ServerConfig, app: &str) -> Environment<'a> {
+        let file = LineFile::new(log, server_config, app, "ENV", "environment");
+        let vars = file
+            .read()
+            .iter()
+            .filter_map(|line| parse_var(line))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+
+        Environment { file, vars }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&String> {
+        self.vars.get(key)
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) {
+        self.vars.insert(key.to_string(), value.to_string());
+    }
+
+    pub fn unset(&mut self, key: &str) -> bool {
+        self.vars.remove(key).is_some()
+    }
+
+    pub fn vars(&self) -> &BTreeMap<String, String> {
+        &self.vars
+    }
+
+    pub fn replace(&mut self, vars: &BTreeMap<String, String>) {
+        self.vars = vars.clone();
+    }
+
+    /// Variables in the `KEY=VALUE` form expected by Docker's container `Env`.
+    pub fn to_docker_env(&self) -> Vec<String> {
+        self.vars.iter().map(|(k, v)| format!("{}={}", k, v)).collect()
+    }
+
+    pub fn save(&self) {
+        self.file.write(self.to_docker_env());
+    }
+}
+
+/// Split a `KEY=VALUE` pair on the first `=`, so values may contain `=` themselves.
+pub fn parse_var(var: &str) -> Option<(&str, &str)> {
+    let (key, value) = var.split_once('=')?;
+    if !is_valid_key(key) || value.contains('\n') {
+        return None;
+    }
+    Some((key, value))
+}
+
+fn is_valid_key(key: &str) -> bool {
+    let mut chars = key.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => chars.all(|c| c.is_ascii_alphanumeric() || c == '_'),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_var_splits_on_first_equals() {
+        assert_eq!(parse_var("KEY=value"), Some(("KEY", "value")));
+        assert_eq!(
+            parse_var("URL=postgres://u:p@h/db?a=b"),
+            Some(("URL", "postgres://u:p@h/db?a=b"))
+        );
+        assert_eq!(parse_var("EMPTY="), Some(("EMPTY", "")));
+        assert_eq!(parse_var("_PRIVATE_1=x"), Some(("_PRIVATE_1", "x")));
+    }
+
+    #[test]
+    fn parse_var_rejects_invalid_keys() {
+        assert_eq!(parse_var("NOVALUE"), None);
+        assert_eq!(parse_var("=value"), None);
+        assert_eq!(parse_var("1KEY=value"), None);
+        assert_eq!(parse_var("MY-KEY=value"), None);
+        assert_eq!(parse_var("MY KEY=value"), None);
+        assert_eq!(parse_var("KEY=multi\nline"), None);
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+
+use bollard::models::{HostConfig, PortBinding, PortMap, RestartPolicy, RestartPolicyNameEnum};
+use bollard::Docker;
+
+use crate::logger::Logger;
+use crate::managed::ManagedContainer;
+use crate::network::{app_network, Network};
+
+/// Publishes an exposed app on its host port. The port is bound by a small TCP forwarder that outlives
+/// deploys and connects to whichever web containers answer to the app name at the time, so replacing
+/// them never has to hand the host port over.
+pub struct Forwarder<'a> {
+    log: &'a Logger,
+    docker: &'a Docker,
+    app: &'a str,
+    container: ManagedContainer<'a>,
+}
+
+impl<'a> Forwarder<'a> {
+    pub fn new(log: &'a Logger, docker: &'a Docker, app: &'a str) -> Forwarder<'a> {
+        Forwarder {
+            log,
+            docker,
+            app,
+            container: ManagedContainer::new(log, docker, format!("ruku-forward-{}", app), "forwarder"),
+        }
+    }
+
+    /// Make sure connections to `host_port` are forwarded to the app's `port`, leaving a forwarder that
+    /// already does so alone.
+    pub async fn ensure(&self, image: &str, host_port: u16, port: u16) {
+        let network = app_network(self.app);
+        Network::new(self.log, self.docker, &network).ensure().await;
+
+        let spec = format!("{}|{}|{}", image, host_port, port);
+        self.container.ensure(&spec, self.config(image, host_port, port)).await;
+    }
+
+    /// The host port the forwarder listens on, if there is one.
+    pub async fn port(&self) -> Option<u16> {
+        self.container
+            .get()
+            .await?
+            .ports?
+            .into_iter()
+            .find_map(|p| p.public_port)
+    }
+
+    /// Stop forwarding, freeing the host port. Nothing happens if the app isn't exposed.
+    pub async fn remove(&self) {
+        self.container.remove().await;
+    }
+
+    fn config(&self, image: &str, host_port: u16, port: u16) -> bollard::container::Config<String> {
+        let exposed_port = format!("{}/tcp", port);
+        let mut port_bindings = PortMap::new();
+        port_bindings.insert(
+            exposed_port.clone(),
+            Some(vec![PortBinding {
+                host_ip: None,
+                host_port: Some(host_port.to_string()),
+            }]),
+        );
+
+        let host_config = HostConfig {
+            port_bindings: Some(port_bindings),
+            network_mode: Some(app_network(self.app)),
+            restart_policy: Some(RestartPolicy {
+                name: Some(RestartPolicyNameEnum::UNLESS_STOPPED),
+                maximum_retry_count: None,
+            }),
+            ..Default::default()
+        };
+
+        // socat resolves the app name in the child it forks for each connection, so every connection
+        // goes to the web containers that are current at the time
+        bollard::container::Config {
+            image: Some(image.to_string()),
+            cmd: Some(vec![
+                format!("TCP-LISTEN:{},fork,reuseaddr", port),
+                format!("TCP:{}:{}", self.app, port),
+            ]),
+            host_config: Some(host_config),
+            exposed_ports: Some(HashMap::from([(exposed_port, HashMap::new())])),
+            ..Default::default()
+        }
+    }
+}
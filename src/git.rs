@@ -91,13 +91,16 @@ cat | RUKU_ROOT="{}" {} git-hook {}
         });
     }
 
-    pub fn cmd_git_hook(&self, app: &str) {
+    /// Check out the pushed `deploy_branch` (`main` or `master` when not set) and return the commit that
+    /// ended up checked out, if any. Pushes to other refs stay in the bare repo without being deployed.
+    pub fn cmd_git_hook(&self, app: &str, deploy_branch: Option<&str>) -> Option<String> {
         let app = sanitize_app_name(app);
 
         let repo_path = self.config.git_root.join(&app);
         let app_path = self.config.apps_root.join(&app);
         let data_path = self.config.data_root.join(&app);
 
+        let mut commit = None;
         let stdin = io::stdin();
         for line in stdin.lock().lines() {
             let line = line.unwrap();
@@ -107,6 +110,26 @@ cat | RUKU_ROOT="{}" {} git-hook {}
             }
             let (_, new_rev, branch) = (parts[0], parts[1], parts[2]);
 
+            let Some(branch) = branch.strip_prefix("refs/heads/") else {
+                continue;
+            };
+            let is_deploy_branch = match deploy_branch {
+                Some(deploy_branch) => branch == deploy_branch,
+                None => branch == "main" || branch == "master",
+            };
+            if !is_deploy_branch {
+                self.log.step(&format!(
+                    "Skipping deploy of branch {}, only {} is deployed",
+                    branch,
+                    deploy_branch.unwrap_or("main or master")
+                ));
+                continue;
+            }
+            // A deleted branch has nothing to deploy
+            if new_rev.chars().all(|c| c == '0') {
+                continue;
+            }
+
             if !app_path.exists() {
                 fs::create_dir_all(&app_path).unwrap_or_else(|e| {
                     self.log.error(&format!("Error creating directory: {}", e));
@@ -127,14 +150,54 @@ cat | RUKU_ROOT="{}" {} git-hook {}
                 });
             }
 
-            self.checkout_latest(&app_path, new_rev, branch);
+            commit = Some(self.checkout_latest(&app_path, new_rev, branch));
         }
+        commit
     }
-    fn checkout_latest(&self, app_path: &Path, new_rev: &str, branch: &str) {
+
+    /// Check out `git_ref` (a branch or a commit) for a manual deploy, or the latest commit of the
+    /// current branch when no ref is given. Returns the commit that ended up checked out.
+    pub fn checkout_ref(&self, app: &str, git_ref: Option<&str>) -> String {
+        let app_path = self.config.apps_root.join(app);
+        if !app_path.exists() {
+            self.log.error(&format!("App {} has not been pushed yet", app));
+            std::process::exit(1);
+        }
+        self.set_git_env(&app_path);
+
+        run_cmd!(git fetch --quiet).unwrap_or_else(|e| {
+            self.log.error(&format!("Error fetching latest code: {}", e));
+            std::process::exit(1);
+        });
+        let current_branch = run_fun!(git rev-parse --abbrev-ref HEAD).unwrap_or_else(|e| {
+            self.log.error(&format!("Error getting current branch: {}", e));
+            std::process::exit(1);
+        });
+
+        let git_ref = git_ref.unwrap_or(current_branch.trim());
+        let remote_branch = format!("refs/remotes/origin/{}", git_ref);
+        if run_cmd!(git rev-parse --verify --quiet $remote_branch > /dev/null).is_ok() {
+            return self.checkout_latest(&app_path, &remote_branch, git_ref);
+        }
+
+        // Not a branch, so it has to be a commit, deployed from the current branch
+        let commit_ref = format!("{}^{{commit}}", git_ref);
+        let commit = run_fun!(git rev-parse --verify --quiet $commit_ref).unwrap_or_else(|_| {
+            self.log.error(&format!("{} is neither a branch nor a commit", git_ref));
+            std::process::exit(1);
+        });
+        self.checkout_latest(&app_path, commit.trim(), current_branch.trim())
+    }
+
+    fn set_git_env(&self, app_path: &Path) {
         unsafe {
             env::set_var("GIT_DIR", app_path.join(".git").display().to_string());
             env::set_var("GIT_WORK_TREE", app_path.display().to_string());
         }
+    }
+
+    fn checkout_latest(&self, app_path: &Path, new_rev: &str, branch: &str) -> String {
+        self.set_git_env(app_path);
 
         let branch = branch.trim_start_matches("refs/heads/");
         self.log
@@ -163,5 +226,10 @@ cat | RUKU_ROOT="{}" {} git-hook {}
             self.log.error(&format!("Error checking out latest code: {}", e));
             std::process::exit(1);
         });
+
+        run_fun!(git rev-parse HEAD).unwrap_or_else(|e| {
+            self.log.error(&format!("Error getting current commit: {}", e));
+            std::process::exit(1);
+        })
     }
 }
@@ -0,0 +1,74 @@
+use std::collections::{HashMap, HashSet};
+
+use bollard::image::{ListImagesOptions, PruneImagesOptions};
+use bollard::Docker;
+
+use crate::container::APP_LABEL;
+use crate::logger::Logger;
+
+pub struct Images<'a> {
+    log: &'a Logger,
+    docker: &'a Docker,
+}
+
+impl<'a> Images<'a> {
+    pub fn new(log: &'a Logger, docker: &'a Docker) -> Images<'a> {
+        Images { log, docker }
+    }
+
+    /// Remove every image of `app` except the ones `keep` refers to. Images still used by a container
+    /// are left alone by Docker, so the running release can never be pruned.
+    pub async fn prune(&self, app: &str, keep: &[String]) {
+        let mut keep_ids = HashSet::new();
+        for image in keep {
+            if let Ok(inspect) = self.docker.inspect_image(image).await {
+                keep_ids.extend(inspect.id);
+            }
+        }
+
+        // Go by the label builds get rather than the name, which other images on the host may share
+        let label = format!("{}={}", APP_LABEL, app);
+        let mut filters = HashMap::new();
+        filters.insert("label", vec![label.as_str()]);
+        let options = Some(ListImagesOptions {
+            filters,
+            ..Default::default()
+        });
+        let images = self.docker.list_images(options).await.unwrap_or_else(|_| {
+            self.log.error("Failed to list images");
+            std::process::exit(1);
+        });
+
+        for image in images.iter().filter(|i| !keep_ids.contains(&i.id)) {
+            // Untag one by one, Docker deletes the image along with its layers once the last tag is gone
+            for tag in &image.repo_tags {
+                match self.docker.remove_image(tag, None, None).await {
+                    Ok(_) => self.log.step(&format!("Removed image {}", tag)),
+                    Err(e) => self.log.error(&format!("Failed to remove image {}: {}", tag, e)),
+                }
+            }
+        }
+    }
+
+    /// Remove untagged images left behind by builds.
+    pub async fn prune_dangling(&self) {
+        let mut filters = HashMap::new();
+        filters.insert("dangling", vec!["true"]);
+        self.docker
+            .prune_images(Some(PruneImagesOptions { filters }))
+            .await
+            .unwrap_or_else(|_| {
+                self.log.error("Failed to prune dangling images");
+                std::process::exit(1);
+            });
+    }
+
+    /// Disk space used by image layers, to report how much a cleanup freed.
+    pub async fn disk_usage(&self) -> i64 {
+        let usage = self.docker.df().await.unwrap_or_else(|_| {
+            self.log.error("Failed to get docker disk usage");
+            std::process::exit(1);
+        });
+        usage.layers_size.unwrap_or(0)
+    }
+}
@@ -0,0 +1,58 @@
+use std::fs;
+use std::path::PathBuf;
+
+use crate::logger::Logger;
+use crate::server_config::ServerConfig;
+
+/// A file of the app's under `<data_root>/<app>/` that holds one entry per line, e.g. `ENV`.
+pub struct LineFile<'a> {
+    log: &'a Logger,
+    path: PathBuf,
+    /// What the file holds, for error messages
+    what: &'static str,
+}
+
+impl<'a> LineFile<'a> {
+    pub fn new(
+        log: &'a Logger,
+        server_config: &ServerConfig,
+        app: &str,
+        name: &str,
+        what: &'static str,
+    ) -> LineFile<'a> {
+        LineFile {
+            log,
+            path: server_config.data_root.join(app).join(name),
+            what,
+        }
+    }
+
+    /// The lines of the file that aren't blank, none if it hasn't been written yet.
+    pub fn read(&self) -> Vec<String> {
+        if !self.path.exists() {
+            return vec![];
+        }
+        let content = fs::read_to_string(&self.path).unwrap_or_else(|e| {
+            self.log.error(&format!("Error reading {} file: {}", self.what, e));
+            std::process::exit(1);
+        });
+        content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(String::from)
+            .collect()
+    }
+
+    pub fn write(&self, lines: impl IntoIterator<Item = String>) {
+        fs::create_dir_all(self.path.parent().unwrap()).unwrap_or_else(|e| {
+            self.log.error(&format!("Error creating directory: {}", e));
+            std::process::exit(1);
+        });
+
+        let content: String = lines.into_iter().map(|line| format!("{}\n", line)).collect();
+        fs::write(&self.path, content).unwrap_or_else(|e| {
+            self.log.error(&format!("Error writing {} file: {}", self.what, e));
+            std::process::exit(1);
+        });
+    }
+}
@@ -0,0 +1,74 @@
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{Seek, Write};
+use std::time::Duration;
+
+use tokio::time::sleep;
+
+use crate::logger::Logger;
+use crate::release::pusher;
+use crate::server_config::ServerConfig;
+
+const LOCK_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Makes deploys of an app take turns, since they share its checkout, image tags and container names.
+/// The lock is an `flock` on `<data_root>/<app>/deploy.lock`, which the kernel releases when its owner
+/// exits however it does, so a crashed deploy never leaves it behind. The file holds the pid of the
+/// owner and what it is doing, for others waiting for it.
+pub struct DeployLock {
+    _file: File,
+}
+
+impl DeployLock {
+    /// Wait for the app's lock and take it. `action` tells others waiting for it what is going on.
+    pub async fn acquire(log: &Logger, server_config: &ServerConfig, app: &str, action: &str) -> DeployLock {
+        let path = server_config.data_root.join(app).join("deploy.lock");
+        fs::create_dir_all(path.parent().unwrap()).unwrap_or_else(|e| {
+            log.error(&format!("Error creating directory: {}", e));
+            std::process::exit(1);
+        });
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .unwrap_or_else(|e| {
+                log.error(&format!("Error opening deploy lock: {}", e));
+                std::process::exit(1);
+            });
+
+        let mut waiting = false;
+        loop {
+            match file.try_lock() {
+                Ok(()) => break,
+                Err(TryLockError::WouldBlock) => {}
+                Err(TryLockError::Error(e)) => {
+                    log.error(&format!("Error taking deploy lock: {}", e));
+                    std::process::exit(1);
+                }
+            }
+
+            if !waiting {
+                let content = fs::read_to_string(&path).unwrap_or_default();
+                let (pid, holder) = content.split_once('\n').unwrap_or(("", ""));
+                log.step(&format!(
+                    "Waiting for deploy lock held by pid {}: {}",
+                    pid,
+                    holder.trim()
+                ));
+                waiting = true;
+            }
+            sleep(LOCK_POLL_INTERVAL).await;
+        }
+
+        let owner = format!("{}\n{} by {}\n", std::process::id(), action, pusher());
+        file.set_len(0)
+            .and_then(|_| file.rewind())
+            .and_then(|_| file.write_all(owner.as_bytes()))
+            .unwrap_or_else(|e| {
+                log.error(&format!("Error writing deploy lock: {}", e));
+                std::process::exit(1);
+            });
+        DeployLock { _file: file }
+    }
+}
@@ -1,25 +1,60 @@
-use std::fs;
+use std::collections::BTreeSet;
+use std::{fs, io};
 
 use bollard::Docker;
+use chrono::{DateTime, Local, Utc};
 use clap::{Parser, Subcommand};
+use serde::Serialize;
 use validator::Validate;
 
 use logger::Logger;
 use server_config::ServerConfig;
 
+use crate::branch::{is_valid_branch, DeployBranch};
 use crate::container::Container;
+use crate::cron::{install_crontab, CronRun, CronRuns, Schedule};
 use crate::deploy::Deploy;
+use crate::domain::{is_valid_domain, Domains};
+use crate::environment::{parse_var, Environment};
+use crate::forwarder::Forwarder;
 use crate::git::Git;
-use crate::misc::sanitize_app_name;
+use crate::image::Images;
+use crate::lock::DeployLock;
+use crate::misc::{format_duration, format_size, parse_since, sanitize_app_name};
 use crate::model::RukuConfig;
+use crate::network::{app_network, Network};
+use crate::port::Ports;
+use crate::process::{parse_procfile, processes, RELEASE, WEB};
+use crate::proxy::{Proxy, Route};
+use crate::release::{Release, Releases};
+use crate::scale::{parse_count, Scale};
 
+mod branch;
 mod container;
+mod cron;
 mod deploy;
+mod domain;
+mod environment;
+mod forwarder;
 mod git;
+mod image;
+mod line_file;
+mod lock;
 mod logger;
+mod managed;
 mod misc;
 mod model;
+mod network;
+mod port;
+mod process;
+mod proxy;
+mod release;
+mod scale;
 mod server_config;
+mod terminal;
+
+/// Upper bound for `ps:scale`, to catch typos before they start hundreds of containers
+const MAX_REPLICAS: usize = 50;
 
 #[derive(Parser)]
 #[command(version, about = "A CLI app for managing your server.")]
@@ -31,28 +66,178 @@ struct Cli {
 /// Enum representing the various commands that can be executed by the CLI.
 #[derive(Subcommand)]
 enum Command {
-    /// Show logs
-    Logs,
-    /// Set a configuration variable, e.g, VAR=12
+    /// Show application logs
+    Logs {
+        /// The application name
+        app: String,
+        /// Keep streaming new log lines
+        #[arg(short, long)]
+        follow: bool,
+        /// Number of lines to show from the end of the logs
+        #[arg(short = 'n', long)]
+        tail: Option<usize>,
+        /// Only show logs since a UNIX timestamp or a relative duration, e.g, 10m, 2h
+        #[arg(long)]
+        since: Option<String>,
+        /// Prefix every line with its timestamp
+        #[arg(short, long)]
+        timestamps: bool,
+        /// The process type to show logs of, e.g, worker
+        #[arg(short, long, default_value = WEB)]
+        process: String,
+        /// Which replica of the process to show logs of
+        #[arg(short, long, default_value_t = 1)]
+        replica: usize,
+    },
+    /// Set configuration variables, e.g, VAR=12
     #[command(name = "config:set")]
     ConfigSet {
-        /// The configuration variable in the form KEY=VALUE
-        var: String,
+        /// The application name
+        app: String,
+        /// The configuration variables in the form KEY=VALUE
+        #[arg(required = true)]
+        vars: Vec<String>,
+        /// Don't restart the application, e.g, to batch several changes
+        #[arg(long)]
+        no_restart: bool,
     },
     /// Get a configuration variable
     #[command(name = "config:get")]
     ConfigGet {
+        /// The application name
+        app: String,
         /// The configuration variable name
         key: String,
     },
-    /// Run the application
-    Run,
-    /// Deploy the application
-    Deploy,
+    /// Remove configuration variables
+    #[command(name = "config:unset")]
+    ConfigUnset {
+        /// The application name
+        app: String,
+        /// The configuration variable names
+        #[arg(required = true)]
+        keys: Vec<String>,
+        /// Don't restart the application, e.g, to batch several changes
+        #[arg(long)]
+        no_restart: bool,
+    },
+    /// List all configuration variables
+    #[command(name = "config:list")]
+    ConfigList {
+        /// The application name
+        app: String,
+    },
+    /// Start the application again from its last release, or run a one-off command against it, e.g,
+    /// ruku run app -- rake db:migrate
+    #[command(visible_alias = "start")]
+    Run {
+        /// The application name
+        app: String,
+        /// Allocate a TTY, for interactive commands like consoles
+        #[arg(short, long, requires = "command")]
+        tty: bool,
+        /// The command to run in a throwaway container from the current release
+        #[arg(last = true)]
+        command: Vec<String>,
+    },
+    /// Set how many containers to run per process type, e.g, web=3 worker=2, or show the current counts
+    #[command(name = "ps:scale")]
+    PsScale {
+        /// The application name
+        app: String,
+        /// The counts in the form PROCESS=COUNT
+        counts: Vec<String>,
+    },
+    /// Rebuild and deploy the application from its git repository without a push
+    Deploy {
+        /// The application name
+        app: String,
+        /// The branch or commit to deploy, defaults to the latest commit of the current branch
+        #[arg(long = "ref")]
+        git_ref: Option<String>,
+    },
     /// Stop the application
-    Stop,
-    /// Destroy the application
-    Destroy,
+    Stop {
+        /// The application name
+        app: String,
+    },
+    /// Destroy the application along with its images, code and data
+    Destroy {
+        /// The application name
+        app: String,
+        /// Don't ask for confirmation
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Route domains to an application through the proxy
+    #[command(name = "domains:add")]
+    DomainsAdd {
+        /// The application name
+        app: String,
+        /// The domains, e.g, example.com
+        #[arg(required = true)]
+        domains: Vec<String>,
+    },
+    /// Stop routing domains to an application
+    #[command(name = "domains:remove")]
+    DomainsRemove {
+        /// The application name
+        app: String,
+        /// The domains
+        #[arg(required = true)]
+        domains: Vec<String>,
+    },
+    /// List the domains of an application
+    #[command(name = "domains:list")]
+    DomainsList {
+        /// The application name
+        app: String,
+    },
+    /// List all applications with their status
+    Apps {
+        /// Print the list as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Remove old images of every application and dangling build layers
+    Gc,
+    /// List the releases of an application
+    Releases {
+        /// The application name
+        app: String,
+    },
+    /// Roll back to an earlier release without rebuilding
+    Rollback {
+        /// The application name
+        app: String,
+        /// The release number to roll back to, defaults to the one before the current release
+        release: Option<u32>,
+    },
+    /// List the cron jobs of an application with their last run, or show the last output of one
+    #[command(name = "cron:list")]
+    CronList {
+        /// The application name
+        app: String,
+        /// The job to show the last output of
+        job: Option<String>,
+    },
+    /// Run the cron jobs that are due, invoked every minute from the crontab
+    #[command(name = "cron:tick")]
+    CronTick,
+    /// Set the branch whose pushes get deployed, overriding deploy_branch in ruku.yml
+    #[command(name = "branch:set")]
+    BranchSet {
+        /// The application name, it doesn't have to be pushed yet
+        app: String,
+        /// The branch name, e.g, production
+        branch: String,
+    },
+    /// Deploy the branch set in ruku.yml again, or main or master when there is none
+    #[command(name = "branch:unset")]
+    BranchUnset {
+        /// The application name
+        app: String,
+    },
     /// Git hook
     #[command(name = "git-hook")]
     GitHook {
@@ -86,39 +271,334 @@ async fn main() {
     let cli = Cli::parse();
 
     match &cli.command {
-        Command::Logs => {
-            println!("Showing logs...");
-        }
-        Command::ConfigSet { var } => {
-            println!("Setting configuration: {}", var);
-            // Parse `var` into key and value
-            let parts: Vec<&str> = var.split('=').collect();
-            if parts.len() == 2 {
-                let key = parts[0];
-                let value = parts[1];
-                println!("Setting {} to {}", key, value);
+        Command::Logs {
+            app,
+            follow,
+            tail,
+            since,
+            timestamps,
+            process,
+            replica,
+        } => {
+            let since = since.as_deref().map_or(Ok(0), parse_since).unwrap_or_else(|e| {
+                log.error(&e);
+                std::process::exit(1);
+            });
+            let app = get_app_name(&log, app, &server_config);
+            let config = load_ruku_config(&log, &app, &server_config);
+            let env = Environment::load(&log, &server_config, &app);
+            let docker = load_docker(&log).await;
+
+            let scale = Scale::load(&log, &server_config, &app);
+            let container = Container::new(&log, &app, &docker, &config, &env, &scale);
+            container
+                .logs(process, *replica, *follow, *tail, since, *timestamps)
+                .await;
+        }
+        Command::ConfigSet { app, vars, no_restart } => {
+            let app = get_app_name(&log, app, &server_config);
+            let mut env = Environment::load(&log, &server_config, &app);
+
+            // Validate everything first so a typo doesn't leave a half-applied change
+            let parsed: Vec<(&str, &str)> = vars
+                .iter()
+                .map(|var| {
+                    parse_var(var).unwrap_or_else(|| {
+                        log.error(&format!("Invalid format '{}'. Use KEY=VALUE", var));
+                        std::process::exit(1);
+                    })
+                })
+                .collect();
+            for (key, value) in parsed {
+                env.set(key, value);
+                log.step(&format!("Setting {}", key));
+            }
+            env.save();
+
+            if !*no_restart {
+                restart(&log, &app, &server_config, &env).await;
+            }
+        }
+        Command::ConfigGet { app, key } => {
+            let app = get_app_name(&log, app, &server_config);
+            let env = Environment::load(&log, &server_config, &app);
+            match env.get(key) {
+                Some(value) => println!("{}", value),
+                None => {
+                    log.error(&format!("{} is not set", key));
+                    std::process::exit(1);
+                }
+            }
+        }
+        Command::ConfigUnset { app, keys, no_restart } => {
+            let app = get_app_name(&log, app, &server_config);
+            let mut env = Environment::load(&log, &server_config, &app);
+            for key in keys {
+                if env.unset(key) {
+                    log.step(&format!("Unsetting {}", key));
+                } else {
+                    log.error(&format!("{} is not set", key));
+                }
+            }
+            env.save();
+
+            if !*no_restart {
+                restart(&log, &app, &server_config, &env).await;
+            }
+        }
+        Command::ConfigList { app } => {
+            let app = get_app_name(&log, app, &server_config);
+            let env = Environment::load(&log, &server_config, &app);
+            for var in env.to_docker_env() {
+                println!("{}", var);
+            }
+        }
+        Command::Run { app, tty, command } => {
+            let app = get_app_name(&log, app, &server_config);
+            if command.is_empty() {
+                start(&log, &app, &server_config).await;
+            } else {
+                let exit_code = run_command(&log, &app, command, *tty, &server_config).await;
+                std::process::exit(exit_code);
+            }
+        }
+        Command::PsScale { app, counts } => {
+            let app = get_app_name(&log, app, &server_config);
+            let config = load_ruku_config(&log, &app, &server_config);
+            let processes = processes(&config);
+            let mut scale = Scale::load(&log, &server_config, &app);
+
+            if counts.is_empty() {
+                for process in &processes {
+                    println!("{}={}", process.name, scale.get(&process.name));
+                }
+                return;
+            }
+
+            let parsed: Vec<(&str, usize)> = counts
+                .iter()
+                .map(|pair| {
+                    parse_count(pair).unwrap_or_else(|| {
+                        log.error(&format!("Invalid format '{}'. Use PROCESS=COUNT", pair));
+                        std::process::exit(1);
+                    })
+                })
+                .collect();
+            for (process, count) in parsed.iter() {
+                if !processes.iter().any(|p| p.name == *process) {
+                    log.error(&format!("{} has no {} process", app, process));
+                    std::process::exit(1);
+                }
+                if *count > MAX_REPLICAS {
+                    log.error(&format!("A process can't run more than {} containers", MAX_REPLICAS));
+                    std::process::exit(1);
+                }
+            }
+            for (process, count) in parsed {
+                scale.set(process, count);
+                log.step(&format!("Scaling {} to {}", process, count));
+            }
+            scale.save();
+
+            let env = Environment::load(&log, &server_config, &app);
+            restart(&log, &app, &server_config, &env).await;
+        }
+        Command::Deploy { app, git_ref } => {
+            let app = get_app_name(&log, app, &server_config);
+            let _lock = DeployLock::acquire(&log, &server_config, &app, "deploy").await;
+            let commit = git.checkout_ref(&app, git_ref.as_deref());
+            deploy(&log, &app, &commit, &server_config).await;
+        }
+        Command::Stop { app } => {
+            let app = get_app_name(&log, app, &server_config);
+            let _lock = DeployLock::acquire(&log, &server_config, &app, "stop").await;
+            let config = load_ruku_config(&log, &app, &server_config);
+            let env = Environment::load(&log, &server_config, &app);
+            let docker = load_docker(&log).await;
+
+            log.section("Stopping application");
+            let scale = Scale::load(&log, &server_config, &app);
+            let container = Container::new(&log, &app, &docker, &config, &env, &scale);
+            container.end().await;
+            Forwarder::new(&log, &docker, &app).remove().await;
+        }
+        Command::Destroy { app, force } => {
+            let app = get_app_name(&log, app, &server_config);
+            if !*force && !confirm(&app) {
+                log.error("Confirmation did not match, aborting");
+                std::process::exit(1);
+            }
+            destroy(&log, &app, &server_config).await;
+        }
+        Command::DomainsAdd { app, domains } => {
+            let app = get_app_name(&log, app, &server_config);
+            let routes = get_routes(&log, &server_config);
+            let mut stored = Domains::load(&log, &server_config, &app);
+            for domain in domains {
+                if !is_valid_domain(domain) {
+                    log.error(&format!("{} is not a valid domain", domain));
+                    std::process::exit(1);
+                }
+                if let Some(owner) = routes
+                    .iter()
+                    .find(|r| r.app != app && r.domains.contains(&domain.to_lowercase()))
+                {
+                    log.error(&format!("{} is already used by {}", domain, owner.app));
+                    std::process::exit(1);
+                }
+                if stored.add(domain) {
+                    log.step(&format!("Adding {}", domain));
+                }
+            }
+            stored.save();
+            sync_proxy(&log, &server_config).await;
+        }
+        Command::DomainsRemove { app, domains } => {
+            let app = get_app_name(&log, app, &server_config);
+            let mut stored = Domains::load(&log, &server_config, &app);
+            for domain in domains {
+                if stored.remove(domain) {
+                    log.step(&format!("Removing {}", domain));
+                } else {
+                    log.error(&format!("{} was not added with domains:add", domain));
+                }
+            }
+            stored.save();
+            sync_proxy(&log, &server_config).await;
+        }
+        Command::DomainsList { app } => {
+            let app = get_app_name(&log, app, &server_config);
+            let routes = get_routes(&log, &server_config);
+            if let Some(route) = routes.iter().find(|r| r.app == app) {
+                for domain in &route.domains {
+                    println!("{}", domain);
+                }
+            }
+        }
+        Command::Apps { json } => {
+            let apps = get_apps_info(&log, &server_config).await;
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&apps).unwrap());
             } else {
-                log.error("Invalid format. Use KEY=VALUE");
+                print_apps_table(&apps);
             }
         }
-        Command::ConfigGet { key } => {
-            println!("Getting configuration for: {}", key);
+        Command::Gc => {
+            let docker = get_docker(&log).await;
+            let images = Images::new(&log, &docker);
+            let before = images.disk_usage().await;
+
+            for app in list_apps(&log, &server_config) {
+                log.section(&format!("Cleaning up {}", app));
+                prune_images(&log, &app, &images, &server_config).await;
+            }
+            images.prune_dangling().await;
+
+            let freed = (before - images.disk_usage().await).max(0) as u64;
+            log.step(&format!("Freed {}", format_size(freed)));
         }
-        Command::Run => {
-            log.section("Running application");
+        Command::Releases { app } => {
+            let app = get_app_name(&log, app, &server_config);
+            let releases = Releases::load(&log, &server_config, &app);
+            let current = releases.current().map(|r| r.number);
+            for release in releases.all().iter().rev() {
+                let marker = if Some(release.number) == current { "*" } else { " " };
+                let note = release
+                    .rollback_of
+                    .map_or(String::new(), |n| format!(" (rollback to v{})", n));
+                println!(
+                    "{} v{:<4} {:.7}  {}  {}  {}{}",
+                    marker,
+                    release.number,
+                    release.commit,
+                    release.image,
+                    release.created_at.format("%Y-%m-%d %H:%M:%S UTC"),
+                    release.pusher,
+                    note
+                );
+            }
+        }
+        Command::Rollback { app, release } => {
+            let app = get_app_name(&log, app, &server_config);
+            rollback(&log, &app, *release, &server_config).await;
+        }
+        Command::CronList { app, job } => {
+            let app = get_app_name(&log, app, &server_config);
+            let config = load_ruku_config(&log, &app, &server_config);
+            let runs = CronRuns::new(&log, &server_config, &app);
+
+            if let Some(job) = job {
+                if !config.cron.contains_key(job) {
+                    log.error(&format!("{} has no {} cron job", app, job));
+                    std::process::exit(1);
+                }
+                match runs.last(job) {
+                    Some(run) => print!("{}", run.output),
+                    None => log.step(&format!("{} has not run yet", job)),
+                }
+                return;
+            }
+
+            let rows = config
+                .cron
+                .iter()
+                .map(|(name, job)| {
+                    let last = runs.last(name);
+                    vec![
+                        name.clone(),
+                        job.schedule.clone(),
+                        last.as_ref().map_or("-".to_string(), |r| {
+                            r.started_at.format("%Y-%m-%d %H:%M:%S UTC").to_string()
+                        }),
+                        last.as_ref().map_or("-".to_string(), |r| {
+                            r.exit_code.map_or("failed to start".to_string(), |c| c.to_string())
+                        }),
+                        job.command.clone(),
+                    ]
+                })
+                .collect();
+            print_table(&["NAME", "SCHEDULE", "LAST RUN", "EXIT", "COMMAND"], rows);
         }
-        Command::Deploy => {
-            log.section("Starting deployment");
+        Command::CronTick => {
+            cron_tick(&log, &server_config).await;
         }
-        Command::Stop => {
-            log.section("Stopping application...");
+        Command::BranchSet { app, branch } => {
+            // Unlike other commands this works before the first push, so that push can already deploy
+            let app = sanitize_app_name(app);
+            if app.is_empty() {
+                log.error("Invalid app name");
+                std::process::exit(1);
+            }
+            if !is_valid_branch(branch) {
+                log.error(&format!("{} is not a valid branch name", branch));
+                std::process::exit(1);
+            }
+            let _lock = DeployLock::acquire(&log, &server_config, &app, "branch:set").await;
+            let mut deploy_branch = DeployBranch::load(&log, &server_config, &app);
+            deploy_branch.set(Some(branch));
+            deploy_branch.save();
+            log.step(&format!("Pushes to {} will be deployed", branch));
         }
-        Command::Destroy => {
-            println!("Destroying application...");
+        Command::BranchUnset { app } => {
+            let app = get_app_name(&log, app, &server_config);
+            let _lock = DeployLock::acquire(&log, &server_config, &app, "branch:unset").await;
+            let mut deploy_branch = DeployBranch::load(&log, &server_config, &app);
+            deploy_branch.set(None);
+            deploy_branch.save();
+            log.step(&format!(
+                "Pushes to {} will be deployed",
+                get_deploy_branch(&log, &app, &server_config)
+                    .as_deref()
+                    .unwrap_or("main or master")
+            ));
         }
         Command::GitHook { repo } => {
-            git.cmd_git_hook(repo);
-            deploy(&log, repo, &server_config).await;
+            // Held from the checkout on, which concurrent pushes would otherwise fight over
+            let _lock = DeployLock::acquire(&log, &server_config, &sanitize_app_name(repo), "push").await;
+            let deploy_branch = get_deploy_branch(&log, &sanitize_app_name(repo), &server_config);
+            if let Some(commit) = git.cmd_git_hook(repo, deploy_branch.as_deref()) {
+                deploy(&log, repo, &commit, &server_config).await;
+            }
         }
         Command::GitReceivePack { repo } => {
             log.section("... RUKU ...");
@@ -132,7 +612,7 @@ async fn main() {
     }
 }
 
-async fn deploy(log: &Logger, repo: &str, server_config: &ServerConfig) {
+async fn deploy(log: &Logger, repo: &str, commit: &str, server_config: &ServerConfig) {
     log.section("Deploying application");
     let config = get_ruku_config(log, repo, server_config);
     let docker = get_docker(log).await;
@@ -140,9 +620,228 @@ async fn deploy(log: &Logger, repo: &str, server_config: &ServerConfig) {
     let app = sanitize_app_name(repo);
     let app_path = server_config.apps_root.join(&app);
 
-    let container = Container::new(log, repo, &docker, &config);
-    let deploy = Deploy::new(log, repo, app_path.as_path().to_str().unwrap(), &config, &container);
-    deploy.run().await;
+    let env = Environment::load(log, server_config, &app);
+    let mut releases = Releases::load(log, server_config, &app);
+
+    let scale = Scale::load(log, server_config, &app);
+    let container = Container::new(log, repo, &docker, &config, &env, &scale);
+    let deploy = Deploy::new(
+        log,
+        repo,
+        app_path.as_path().to_str().unwrap(),
+        &config,
+        &container,
+        commit,
+    );
+    let image = deploy.run().await;
+    expose(log, &docker, &app, &config, server_config).await;
+
+    let release = releases.add(commit, &image, env.vars(), &config, None);
+    log.step(&format!("Released v{}", release.number));
+
+    prune_images(log, &app, &Images::new(log, &docker), server_config).await;
+    sync_proxy(log, server_config).await;
+    if !config.cron.is_empty() {
+        install_crontab(log, server_config);
+    }
+}
+
+/// Recreate the app's container from an earlier release's image and config, without rebuilding.
+async fn rollback(log: &Logger, app: &str, release: Option<u32>, server_config: &ServerConfig) {
+    let _lock = DeployLock::acquire(log, server_config, app, "rollback").await;
+    let mut releases = Releases::load(log, server_config, app);
+    let target = match release {
+        Some(number) => releases.get(number),
+        None => releases.previous(),
+    }
+    .cloned()
+    .unwrap_or_else(|| {
+        log.error("No release to roll back to");
+        std::process::exit(1);
+    });
+
+    log.section(&format!("Rolling back to v{}", target.number));
+    // Releases recorded before their settings were kept fall back to the checkout's
+    let config = target
+        .config
+        .clone()
+        .unwrap_or_else(|| load_ruku_config(log, app, server_config));
+    let docker = get_docker(log).await;
+
+    let mut env = Environment::load(log, server_config, app);
+    env.replace(&target.env);
+
+    let scale = Scale::load(log, server_config, app);
+    let container = Container::new(log, app, &docker, &config, &env, &scale);
+    container.run(&target.image).await;
+    expose(log, &docker, app, &config, server_config).await;
+    env.save();
+
+    let release = releases.add(&target.commit, &target.image, &target.env, &config, Some(target.number));
+    log.step(&format!("Released v{}", release.number));
+
+    sync_proxy(log, server_config).await;
+    if !config.cron.is_empty() {
+        install_crontab(log, server_config);
+    }
+}
+
+/// Publish the app on its host port if its ruku.yml asks for it to be exposed, or stop publishing it.
+async fn expose(log: &Logger, docker: &Docker, app: &str, config: &RukuConfig, server_config: &ServerConfig) {
+    let ports = Ports::new(log, server_config);
+    let forwarder = Forwarder::new(log, docker, app);
+    if !config.expose {
+        ports.release(app);
+        forwarder.remove().await;
+        return;
+    }
+    let host_port = ports.assign(app, config.host_port, forwarder.port().await);
+    forwarder
+        .ensure(&server_config.forwarder_image, host_port, config.port)
+        .await;
+}
+
+/// The branch whose pushes get deployed: the one set with `branch:set`, otherwise the one in the ruku.yml
+/// that is deployed now, so a push can't redirect deploys to its own branch. Nothing means main or master.
+fn get_deploy_branch(log: &Logger, app: &str, server_config: &ServerConfig) -> Option<String> {
+    DeployBranch::load(log, server_config, app)
+        .get()
+        .map(String::from)
+        .or_else(|| read_ruku_config(app, server_config).ok()?.deploy_branch)
+}
+
+/// Sanitize the app name given on the command line and make sure it has been pushed at least once.
+fn get_app_name(log: &Logger, app: &str, server_config: &ServerConfig) -> String {
+    let app = sanitize_app_name(app);
+    if !server_config.git_root.join(&app).exists() {
+        log.error(&format!("App {} does not exist", app));
+        std::process::exit(1);
+    }
+    app
+}
+
+/// Start the app from its current release, e.g. after `ruku stop`.
+async fn start(log: &Logger, app: &str, server_config: &ServerConfig) {
+    let _lock = DeployLock::acquire(log, server_config, app, "start").await;
+    let releases = Releases::load(log, server_config, app);
+    let image = match releases.current() {
+        Some(release) => release.image.clone(),
+        None => {
+            log.error("Application has no release to start, push it first");
+            std::process::exit(1);
+        }
+    };
+
+    let config = load_ruku_config(log, app, server_config);
+    let env = Environment::load(log, server_config, app);
+    let docker = load_docker(log).await;
+
+    let scale = Scale::load(log, server_config, app);
+    let container = Container::new(log, app, &docker, &config, &env, &scale);
+    if container.is_running().await {
+        log.step("Application is already running");
+        return;
+    }
+
+    log.section("Starting application");
+    container.run(&image).await;
+    expose(log, &docker, app, &config, server_config).await;
+}
+
+/// Run a command in a throwaway container from the app's current release, returning its exit code.
+async fn run_command(log: &Logger, app: &str, command: &[String], tty: bool, server_config: &ServerConfig) -> i32 {
+    let releases = Releases::load(log, server_config, app);
+    let Some(release) = releases.current() else {
+        log.error("Application has no release to run the command in, push it first");
+        std::process::exit(1);
+    };
+
+    let config = load_ruku_config(log, app, server_config);
+    let env = Environment::load(log, server_config, app);
+    let scale = Scale::load(log, server_config, app);
+    let docker = load_docker(log).await;
+
+    let container = Container::new(log, app, &docker, &config, &env, &scale);
+    let name = format!("{}-run-{}", app, std::process::id());
+    container
+        .run_attached(&name, &release.image, command, tty)
+        .await
+        .map_or(1, |code| code as i32)
+}
+
+/// Ask the user to type the app name before anything gets deleted.
+fn confirm(app: &str) -> bool {
+    eprint!(
+        "This will permanently delete {} with its images, code and data. Type the app name to confirm: ",
+        app
+    );
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    answer.trim() == app
+}
+
+/// Remove everything ruku keeps for the app: its container, images, git repo, checkout and data.
+async fn destroy(log: &Logger, app: &str, server_config: &ServerConfig) {
+    let _lock = DeployLock::acquire(log, server_config, app, "destroy").await;
+    log.section(&format!("Destroying {}", app));
+    // Only the container name matters for removal, so a broken or missing ruku.yml mustn't block it
+    let config = read_ruku_config(app, server_config).unwrap_or_default();
+    let env = Environment::load(log, server_config, app);
+    let docker = load_docker(log).await;
+
+    let scale = Scale::load(log, server_config, app);
+    let container = Container::new(log, app, &docker, &config, &env, &scale);
+    container.destroy().await;
+    Forwarder::new(log, &docker, app).remove().await;
+    Network::new(log, &docker, &app_network(app)).remove().await;
+    Images::new(log, &docker).prune(app, &[]).await;
+
+    for path in [
+        server_config.git_root.join(app),
+        server_config.apps_root.join(app),
+        server_config.data_root.join(app),
+    ] {
+        if path.exists() {
+            fs::remove_dir_all(&path).unwrap_or_else(|e| {
+                log.error(&format!("Error removing {}: {}", path.display(), e));
+                std::process::exit(1);
+            });
+            log.step(&format!("Removed {}", path.display()));
+        }
+    }
+    sync_proxy(log, server_config).await;
+    log.step(&format!("{} destroyed", app));
+}
+
+/// Recreate the app's container from the already built image so config changes take effect.
+async fn restart(log: &Logger, app: &str, server_config: &ServerConfig, env: &Environment<'_>) {
+    let _lock = DeployLock::acquire(log, server_config, app, "restart").await;
+    let config = load_ruku_config(log, app, server_config);
+    let docker = load_docker(log).await;
+
+    let scale = Scale::load(log, server_config, app);
+    let container = Container::new(log, app, &docker, &config, env, &scale);
+    let Some(running) = container.get().await else {
+        log.step("Application is not deployed yet, changes will apply on the next deploy");
+        return;
+    };
+
+    // Reuse the image that is already deployed rather than building it again. Apps deployed before
+    // releases were recorded fall back to whatever image their container runs.
+    let releases = Releases::load(log, server_config, app);
+    let image = match releases.current() {
+        Some(release) => release.image.clone(),
+        None => running.image.unwrap_or_else(|| {
+            log.error("Failed to get the deployed image");
+            std::process::exit(1);
+        }),
+    };
+
+    log.section("Restarting application");
+    container.run(&image).await;
+    expose(log, &docker, app, &config, server_config).await;
 }
 
 async fn get_docker(log: &Logger) -> Docker {
@@ -170,33 +869,296 @@ async fn load_docker(log: &Logger) -> Docker {
 }
 
 fn get_ruku_config(log: &Logger, repo: &str, server_config: &ServerConfig) -> RukuConfig {
+    let config = read_ruku_config(repo, server_config).unwrap_or_else(|e| {
+        log.error(&e);
+        std::process::exit(1);
+    });
+
+    match config.validate() {
+        Ok(_) => (),
+        Err(e) => {
+            log.error(&format!("Error validating ruku.yml file: {}", e));
+            std::process::exit(1);
+        }
+    };
+
+    // A host port is only taken when another app has it, the app's own running container doesn't count
+    if let Some(host_port) = config.host_port.filter(|_| config.expose) {
+        let app = sanitize_app_name(repo);
+        if let Some(owner) = Ports::new(log, server_config).owner(host_port, &app) {
+            log.error(&format!("Port {} is already used by {}", host_port, owner));
+            std::process::exit(1);
+        }
+    }
+
+    // Two apps claiming a domain would leave the proxy unable to route it
+    let app = sanitize_app_name(repo);
+    let routes = get_routes(log, server_config);
+    for domain in &config.domains {
+        if let Some(owner) = routes
+            .iter()
+            .find(|r| r.app != app && r.domains.contains(&domain.to_lowercase()))
+        {
+            log.error(&format!("Domain {} is already used by {}", domain, owner.app));
+            std::process::exit(1);
+        }
+    }
+
+    config
+}
+
+/// Load the ruku.yml of an app that is already deployed. Validation is left to pushes, so the app can
+/// still be managed while its ruku.yml is being fixed.
+fn load_ruku_config(log: &Logger, app: &str, server_config: &ServerConfig) -> RukuConfig {
+    read_deployed_config(log, app, server_config).unwrap_or_else(|e| {
+        log.error(&e);
+        std::process::exit(1);
+    })
+}
+
+/// The settings the app's current release runs with, which differ from its checkout after a rollback.
+/// Apps without a release, or whose release predates keeping them, fall back to the checkout's ruku.yml.
+fn read_deployed_config(log: &Logger, app: &str, server_config: &ServerConfig) -> Result<RukuConfig, String> {
+    match Releases::load(log, server_config, app).current() {
+        Some(Release {
+            config: Some(config), ..
+        }) => Ok(config.clone()),
+        _ => read_ruku_config(app, server_config),
+    }
+}
+
+/// Read and parse the app's ruku.yml without validating it.
+fn read_ruku_config(repo: &str, server_config: &ServerConfig) -> Result<RukuConfig, String> {
     let repo_path = server_config.apps_root.join(repo);
 
     // Check for the presence of ruku.yml file
     let config_path = repo_path.join("ruku.yml");
     if !config_path.exists() {
-        log.error("ruku.yml file is missing in the repository");
-        std::process::exit(1);
+        return Err("ruku.yml file is missing in the repository".to_string());
     }
 
     // Parse the ruku.yml file
-    let config_content = fs::read_to_string(&config_path).unwrap_or_else(|e| {
-        log.error(&format!("Error reading ruku.yml file: {}", e));
-        std::process::exit(1);
-    });
+    let config_content = fs::read_to_string(&config_path).map_err(|e| format!("Error reading ruku.yml file: {}", e))?;
 
-    let config: RukuConfig = serde_yaml::from_str(&config_content).unwrap_or_else(|e| {
-        log.error(&format!("Error parsing ruku.yml file: {}", e));
-        std::process::exit(1);
-    });
+    let mut config: RukuConfig =
+        serde_yaml::from_str(&config_content).map_err(|e| format!("Error parsing ruku.yml file: {}", e))?;
 
-    match config.validate() {
-        Ok(_) => (),
-        Err(e) => {
-            log.error(&format!("Error validating ruku.yml file: {}", e));
+    // Processes listed in ruku.yml take precedence over a Procfile
+    let procfile_path = repo_path.join("Procfile");
+    if config.processes.is_empty() && procfile_path.exists() {
+        let procfile = fs::read_to_string(&procfile_path).map_err(|e| format!("Error reading Procfile: {}", e))?;
+        config.processes = parse_procfile(&procfile)?;
+        if let Some(release) = config.processes.remove(RELEASE) {
+            config.release.get_or_insert(release);
+        }
+    }
+    Ok(config)
+}
+
+/// Names of all apps on this server, from their git repos and checkouts.
+fn list_apps(log: &Logger, server_config: &ServerConfig) -> Vec<String> {
+    let mut apps = BTreeSet::new();
+    for root in [&server_config.git_root, &server_config.apps_root] {
+        if !root.exists() {
+            continue;
+        }
+
+        let entries = fs::read_dir(root).unwrap_or_else(|e| {
+            log.error(&format!("Error reading {}: {}", root.display(), e));
             std::process::exit(1);
+        });
+        apps.extend(
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().is_dir())
+                .filter_map(|entry| entry.file_name().into_string().ok()),
+        );
+    }
+    apps.into_iter().collect()
+}
+
+/// The domains of every app, from their ruku.yml and `domains:add`, with the port to route them to. Apps whose
+/// settings don't validate are left out.
+fn get_routes(log: &Logger, server_config: &ServerConfig) -> Vec<Route> {
+    let mut routes = vec![];
+    for app in list_apps(log, server_config) {
+        // A broken ruku.yml left in a checkout must not take the proxy down for every app
+        let Some(config) = read_deployed_config(log, &app, server_config)
+            .ok()
+            .filter(|c| c.validate().is_ok())
+        else {
+            continue;
+        };
+        let mut domains: BTreeSet<String> = config.domains.iter().map(|d| d.to_lowercase()).collect();
+        domains.extend(Domains::load(log, server_config, &app).all().iter().cloned());
+
+        routes.push(Route {
+            app,
+            domains: domains.into_iter().collect(),
+            port: config.port,
+        });
+    }
+    routes
+}
+
+/// Point the proxy at the current set of apps and domains.
+async fn sync_proxy(log: &Logger, server_config: &ServerConfig) {
+    let docker = load_docker(log).await;
+    let routes = get_routes(log, server_config);
+    Proxy::new(log, &docker, server_config).sync(&routes).await;
+}
+
+/// A row of `ruku apps`.
+#[derive(Serialize)]
+struct AppInfo {
+    name: String,
+    state: String,
+    port: Option<u16>,
+    image: Option<String>,
+    commit: Option<String>,
+    /// Seconds since the container started
+    uptime: Option<i64>,
+}
+
+async fn get_apps_info(log: &Logger, server_config: &ServerConfig) -> Vec<AppInfo> {
+    let docker = load_docker(log).await;
+
+    let mut apps = vec![];
+    for app in list_apps(log, server_config) {
+        let config = read_deployed_config(log, &app, server_config).unwrap_or_default();
+        let env = Environment::load(log, server_config, &app);
+        let releases = Releases::load(log, server_config, &app);
+
+        let scale = Scale::load(log, server_config, &app);
+        let container = Container::new(log, &app, &docker, &config, &env, &scale);
+        let status = container.status().await;
+        apps.push(AppInfo {
+            state: status.as_ref().map_or("not deployed".to_string(), |s| s.state.clone()),
+            port: Forwarder::new(log, &docker, &app).port().await,
+            image: status.as_ref().and_then(|s| s.image.clone()),
+            commit: releases.current().map(|r| r.commit.clone()),
+            uptime: status
+                .and_then(|s| s.started_at)
+                .map(|t| (Utc::now() - t).num_seconds()),
+            name: app,
+        });
+    }
+    apps
+}
+
+fn print_apps_table(apps: &[AppInfo]) {
+    let rows = apps
+        .iter()
+        .map(|app| {
+            vec![
+                app.name.clone(),
+                app.state.clone(),
+                app.port.map_or("-".to_string(), |p| p.to_string()),
+                app.image.clone().unwrap_or("-".to_string()),
+                app.commit
+                    .as_ref()
+                    .map_or("-".to_string(), |c| c.chars().take(7).collect()),
+                app.uptime.map_or("-".to_string(), format_duration),
+            ]
+        })
+        .collect();
+    print_table(&["NAME", "STATE", "PORT", "IMAGE", "COMMIT", "UPTIME"], rows);
+}
+
+/// Print rows as columns aligned under `header`.
+fn print_table(header: &[&str], rows: Vec<Vec<String>>) {
+    let header: Vec<String> = header.iter().map(|h| h.to_string()).collect();
+    let mut widths: Vec<usize> = header.iter().map(|h| h.len()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
         }
-    };
+    }
 
-    config
+    for row in std::iter::once(&header).chain(&rows) {
+        let line: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    }
+}
+
+/// Start the cron jobs of every app that are due this minute and wait for them all to finish. Jobs of
+/// apps without a release are skipped.
+async fn cron_tick(log: &Logger, server_config: &ServerConfig) {
+    let now = Local::now();
+    let docker = load_docker(log).await;
+
+    let mut due = vec![];
+    for app in list_apps(log, server_config) {
+        let Ok(config) = read_deployed_config(log, &app, server_config) else {
+            continue;
+        };
+        let Some(release) = Releases::load(log, server_config, &app).current().cloned() else {
+            continue;
+        };
+        for (name, job) in &config.cron {
+            if Schedule::parse(&job.schedule).is_ok_and(|s| s.matches(&now)) {
+                due.push((app.clone(), name.clone(), job.command.clone(), release.image.clone()));
+            }
+        }
+    }
+
+    let runs = due
+        .iter()
+        .map(|(app, job, command, image)| run_cron_job(log, server_config, &docker, app, job, command, image));
+    futures_util::future::join_all(runs).await;
+}
+
+async fn run_cron_job(
+    log: &Logger,
+    server_config: &ServerConfig,
+    docker: &Docker,
+    app: &str,
+    job: &str,
+    command: &str,
+    image: &str,
+) {
+    let config = read_deployed_config(log, app, server_config).unwrap_or_default();
+    let env = Environment::load(log, server_config, app);
+    let scale = Scale::load(log, server_config, app);
+    let container = Container::new(log, app, docker, &config, &env, &scale);
+
+    // Leave the last run's result alone rather than recording this tick as a failure
+    let name = format!("{}-cron-{}", app, job);
+    if container.is_running_once(&name).await {
+        log.step(&format!("{} cron job {} is still running, skipping", app, job));
+        return;
+    }
+
+    log.step(&format!("Running {} cron job {}", app, job));
+    let started_at: DateTime<Utc> = Utc::now();
+    let mut output = vec![];
+    let exit_code = container
+        .run_once(&name, image, command, |chunk| {
+            output.extend_from_slice(&chunk.into_bytes())
+        })
+        .await;
+
+    CronRuns::new(log, server_config, app).record(
+        job,
+        CronRun {
+            started_at,
+            finished_at: Utc::now(),
+            exit_code,
+            output: String::from_utf8_lossy(&output).into_owned(),
+        },
+    );
+}
+
+/// Remove the app's images that fall outside the retention window.
+async fn prune_images(log: &Logger, app: &str, images: &Images<'_>, server_config: &ServerConfig) {
+    let keep_releases = read_ruku_config(app, server_config)
+        .ok()
+        .and_then(|c| c.keep_releases)
+        .unwrap_or(server_config.keep_releases);
+    let releases = Releases::load(log, server_config, app);
+    images.prune(app, &releases.recent_images(keep_releases)).await;
 }
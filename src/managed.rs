@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+
+use bollard::container::{
+    CreateContainerOptions, ListContainersOptions, RemoveContainerOptions, StartContainerOptions,
+};
+use bollard::image::CreateImageOptions;
+use bollard::models::ContainerSummary;
+use bollard::Docker;
+use futures_util::StreamExt;
+
+use crate::logger::Logger;
+
+/// Label holding the settings a managed container was created with, to spot when it must be recreated
+const SPEC_LABEL: &str = "ruku.spec";
+
+/// What `ManagedContainer::ensure` found and did.
+pub enum Ensured {
+    /// The container was missing or out of date and has been created anew
+    Created,
+    /// The container was up to date but stopped, and has been started
+    Started,
+    /// The container was up to date and running already
+    Running,
+}
+
+/// A container ruku keeps a single instance of next to the apps, e.g. the proxy. It is recreated from
+/// scratch whenever the settings it was created from change.
+pub struct ManagedContainer<'a> {
+    log: &'a Logger,
+    docker: &'a Docker,
+    name: String,
+    /// What the container is, for messages
+    what: &'static str,
+}
+
+impl<'a> ManagedContainer<'a> {
+    pub fn new(log: &'a Logger, docker: &'a Docker, name: String, what: &'static str) -> ManagedContainer<'a> {
+        ManagedContainer {
+            log,
+            docker,
+            name,
+            what,
+        }
+    }
+
+    pub async fn get(&self) -> Option<ContainerSummary> {
+        find_container(self.log, self.docker, &self.name).await
+    }
+
+    /// Make sure the container runs with `config`, which `spec` sums up: everything that requires
+    /// recreating the container when it changes.
+    pub async fn ensure(&self, spec: &str, mut config: bollard::container::Config<String>) -> Ensured {
+        match self.get().await {
+            Some(container)
+                if container
+                    .labels
+                    .as_ref()
+                    .and_then(|l| l.get(SPEC_LABEL))
+                    .map(String::as_str)
+                    == Some(spec) =>
+            {
+                if container.state.as_deref() == Some("running") {
+                    return Ensured::Running;
+                }
+                self.start().await;
+                return Ensured::Started;
+            }
+            Some(_) => {
+                self.log
+                    .step(&format!("Settings of the {} changed, recreating it", self.what));
+                self.remove().await;
+            }
+            None => {}
+        }
+
+        if let Some(image) = config.image.clone() {
+            self.pull(&image).await;
+        }
+        config
+            .labels
+            .get_or_insert_with(HashMap::new)
+            .insert(SPEC_LABEL.to_string(), spec.to_string());
+        let options = CreateContainerOptions {
+            name: self.name.as_str(),
+            platform: None,
+        };
+        self.docker
+            .create_container(Some(options), config)
+            .await
+            .unwrap_or_else(|e| {
+                self.log
+                    .error(&format!("Failed to create {} container: {}", self.what, e));
+                std::process::exit(1);
+            });
+        self.log.step(&format!("Created {} container", self.what));
+        self.start().await;
+        Ensured::Created
+    }
+
+    /// Remove the container whatever state it is in, doing nothing if there is none.
+    pub async fn remove(&self) {
+        if self.get().await.is_none() {
+            return;
+        }
+        self.docker
+            .remove_container(
+                &self.name,
+                Some(RemoveContainerOptions {
+                    force: true,
+                    ..Default::default()
+                }),
+            )
+            .await
+            .unwrap_or_else(|e| {
+                self.log
+                    .error(&format!("Failed to remove {} container: {}", self.what, e));
+                std::process::exit(1);
+            });
+        self.log.step(&format!("Removed {} container", self.what));
+    }
+
+    async fn pull(&self, image: &str) {
+        self.log.step(&format!("Pulling {} image {}", self.what, image));
+        let options = Some(CreateImageOptions {
+            from_image: image,
+            ..Default::default()
+        });
+        let mut stream = self.docker.create_image(options, None, None);
+        while let Some(result) = stream.next().await {
+            result.unwrap_or_else(|e| {
+                self.log.error(&format!("Failed to pull {} image: {}", self.what, e));
+                std::process::exit(1);
+            });
+        }
+    }
+
+    async fn start(&self) {
+        self.docker
+            .start_container(&self.name, None::<StartContainerOptions<String>>)
+            .await
+            .unwrap_or_else(|e| {
+                self.log.error(&format!("Failed to start {}: {}", self.what, e));
+                std::process::exit(1);
+            });
+        self.log.step(&format!("Started {}", self.what));
+    }
+}
+
+/// The container called exactly `name`, whatever its state.
+pub async fn find_container(log: &Logger, docker: &Docker, name: &str) -> Option<ContainerSummary> {
+    // Docker matches names as a regex, so anchor it to avoid picking up e.g. `<name>-next`
+    let name = format!("^/{}$", name);
+    let mut filters = HashMap::new();
+    filters.insert("name", vec![name.as_str()]);
+
+    let options = Some(ListContainersOptions {
+        all: true,
+        filters,
+        limit: Some(1),
+        ..Default::default()
+    });
+    let containers = docker.list_containers(options).await.unwrap_or_else(|_| {
+        log.error("Failed to list containers");
+        std::process::exit(1);
+    });
+    containers.into_iter().next()
+}
@@ -1,9 +1,11 @@
-pub fn get_image_name_with_version(image: &str, version: &Option<String>) -> String {
-    let mut image_version = "latest";
-    if let Some(v) = version {
-        image_version = v;
-    }
-    format!("{}:{}", image, image_version)
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub fn get_image_name_with_commit(image: &str, commit: &str) -> String {
+    format!("{}:{}", image, commit)
+}
+
+pub fn get_image_name_with_version(image: &str, version: &Option<String>) -> Option<String> {
+    version.as_ref().map(|v| format!("{}:{}", image, v))
 }
 
 pub fn sanitize_app_name(app: &str) -> String {
@@ -14,3 +16,110 @@ pub fn sanitize_app_name(app: &str) -> String {
         .trim_end()
         .to_string()
 }
+
+/// Parse a `--since` value into a UNIX timestamp. Accepts either an absolute
+/// timestamp (`1719878400`) or a relative duration (`30s`, `10m`, `2h`, `1d`).
+pub fn parse_since(since: &str) -> Result<i64, String> {
+    let since = since.trim();
+    if let Ok(timestamp) = since.parse::<i64>() {
+        return Ok(timestamp);
+    }
+
+    let invalid = || format!("Invalid time '{}'. Use a UNIX timestamp or a duration like 10m", since);
+    let (index, unit) = since.char_indices().last().ok_or_else(invalid)?;
+    let amount: i64 = since[..index].parse().map_err(|_| invalid())?;
+    let multiplier = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 60 * 60 * 24,
+        _ => return Err(invalid()),
+    };
+    let seconds = amount.checked_mul(multiplier).ok_or_else(invalid)?;
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| e.to_string())?
+        .as_secs() as i64;
+    now.checked_sub(seconds).ok_or_else(invalid)
+}
+
+/// Human readable size, e.g. `1.5 GB`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[unit])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// Parse a Docker style size, e.g. `512m` or `1g`, into bytes. A bare number is taken as bytes.
+pub fn parse_size(size: &str) -> Option<i64> {
+    let size = size.trim().to_lowercase();
+    let (amount, multiplier) = match size.chars().last()? {
+        'b' => (&size[..size.len() - 1], 1),
+        'k' => (&size[..size.len() - 1], 1024),
+        'm' => (&size[..size.len() - 1], 1024 * 1024),
+        'g' => (&size[..size.len() - 1], 1024 * 1024 * 1024),
+        _ => (size.as_str(), 1),
+    };
+    let amount: i64 = amount.parse().ok()?;
+    amount.checked_mul(multiplier).filter(|bytes| *bytes > 0)
+}
+
+/// Compact human readable duration, e.g. `3d 4h`.
+pub fn format_duration(seconds: i64) -> String {
+    let (days, hours, minutes) = (seconds / 86400, seconds % 86400 / 3600, seconds % 3600 / 60);
+    if days > 0 {
+        format!("{}d {}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else if minutes > 0 {
+        format!("{}m", minutes)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_since_rejects_invalid_durations() {
+        assert_eq!(parse_since("1719878400"), Ok(1719878400));
+        assert!(parse_since("10m").is_ok());
+        assert!(parse_since("5µ").is_err());
+        assert!(parse_since("µ").is_err());
+        assert!(parse_since("").is_err());
+        assert!(parse_since("9223372036854775807d").is_err());
+    }
+
+    #[test]
+    fn parse_size_reads_docker_units() {
+        assert_eq!(parse_size("512"), Some(512));
+        assert_eq!(parse_size("512b"), Some(512));
+        assert_eq!(parse_size("4k"), Some(4 * 1024));
+        assert_eq!(parse_size("256m"), Some(256 * 1024 * 1024));
+        assert_eq!(parse_size(" 2G "), Some(2 * 1024 * 1024 * 1024));
+    }
+
+    #[test]
+    fn parse_size_rejects_invalid_sizes() {
+        assert_eq!(parse_size(""), None);
+        assert_eq!(parse_size("m"), None);
+        assert_eq!(parse_size("0m"), None);
+        assert_eq!(parse_size("-1g"), None);
+        assert_eq!(parse_size("1.5g"), None);
+        assert_eq!(parse_size("1t"), None);
+        assert_eq!(parse_size("1µ"), None);
+        assert_eq!(parse_size("9223372036854775807g"), None);
+    }
+}
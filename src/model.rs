@@ -1,18 +1,307 @@
-use port_selector::is_free;
-use serde::Deserialize;
+use std::collections::BTreeMap;
+
+use bollard::models::{RestartPolicy, RestartPolicyNameEnum};
+use serde::{Deserialize, Serialize};
 use validator::{Validate, ValidationError};
 
-#[derive(Debug, Validate, Deserialize)]
+use crate::cron::Schedule;
+use crate::domain::is_valid_domain;
+use crate::misc::parse_size;
+use crate::process::{is_valid_process_name, RELEASE};
+
+#[derive(Debug, Default, Clone, Validate, Serialize, Deserialize)]
 pub struct RukuConfig {
-    #[validate(range(min = 1024, max = 65535), custom(function = "validate_port"))]
+    /// Port the app listens on inside its container
+    #[validate(range(min = 1))]
     pub port: u16,
     #[validate(length(min = 1, max = 20))]
     pub version: Option<String>,
+    #[validate(nested)]
+    pub healthcheck: Option<HealthCheck>,
+    /// How many release images to keep around for rollbacks, overrides the server setting
+    #[validate(range(min = 1, max = 100))]
+    pub keep_releases: Option<usize>,
+    /// Publish `port` on the host, otherwise the app is only reachable through the proxy
+    #[serde(default)]
+    pub expose: bool,
+    /// Host port to publish on when exposed, a free one is allocated when left out
+    #[validate(range(min = 1024))]
+    pub host_port: Option<u16>,
+    /// Domains the proxy routes to this app
+    #[validate(custom(function = "validate_domains"))]
+    #[serde(default)]
+    pub domains: Vec<String>,
+    /// Command of each process type, e.g. `web` and `worker`, taken from the Procfile when left out
+    #[validate(custom(function = "validate_processes"))]
+    #[serde(default)]
+    pub processes: BTreeMap<String, String>,
+    /// Limits applied to every container of the app and to its builds
+    #[validate(nested)]
+    pub resources: Option<Resources>,
+    /// When Docker restarts the app's containers: `no`, `on-failure[:max]`, `always` or `unless-stopped`,
+    /// the default
+    #[validate(custom(function = "validate_restart"))]
+    pub restart: Option<String>,
+    /// Branch whose pushes get deployed, pushes to other branches are only stored. Defaults to `main` or
+    /// `master`, and `ruku branch:set` overrides it.
+    #[validate(length(min = 1))]
+    pub deploy_branch: Option<String>,
+    /// Command run in a one-off container from a new build before it replaces the live containers, e.g.
+    /// database migrations. The deploy is aborted when it fails. Taken from the Procfile's `release`
+    /// entry when left out.
+    #[validate(length(min = 1))]
+    pub release: Option<String>,
+    /// Scheduled jobs by name, each run in a one-off container from the current release
+    #[validate(custom(function = "validate_cron"))]
+    #[serde(default)]
+    pub cron: BTreeMap<String, CronJob>,
+}
+
+impl RukuConfig {
+    pub fn restart_policy(&self) -> RestartPolicy {
+        self.restart
+            .as_deref()
+            .and_then(parse_restart_policy)
+            .unwrap_or(RestartPolicy {
+                name: Some(RestartPolicyNameEnum::UNLESS_STOPPED),
+                maximum_retry_count: None,
+            })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CronJob {
+    /// When to run, e.g. `0 3 * * *` or `@hourly`
+    pub schedule: String,
+    /// Command run with bash
+    pub command: String,
+}
+
+/// Resource limits of the app's containers, anything left out is unlimited.
+#[derive(Debug, Clone, Validate, Serialize, Deserialize)]
+pub struct Resources {
+    /// Memory limit, e.g. `512m` or `1g`
+    #[validate(custom(function = "validate_memory"))]
+    pub memory: Option<String>,
+    /// Number of CPUs the app may use, enforced as a CFS quota, e.g. `1.5`
+    #[validate(range(min = 0.01, max = 1024.0))]
+    pub cpus: Option<f64>,
+    /// Relative CPU weight when CPUs are contended, Docker's default is 1024
+    #[validate(range(min = 2, max = 262144))]
+    pub cpu_shares: Option<i64>,
+    /// Maximum number of processes and threads in a container
+    #[validate(range(min = 1))]
+    pub pids: Option<i64>,
+}
+
+impl Resources {
+    /// Period of the CFS scheduler in microseconds, `cpus` is turned into a quota of it
+    pub const CPU_PERIOD: i64 = 100_000;
+
+    pub fn memory_bytes(&self) -> Option<i64> {
+        self.memory.as_deref().and_then(parse_size)
+    }
+
+    pub fn cpu_quota(&self) -> Option<i64> {
+        self.cpus.map(|cpus| (cpus * Self::CPU_PERIOD as f64) as i64)
+    }
+}
+
+/// Health check run by Docker inside the container. An HTTP request is made when `path` is set,
+/// otherwise a plain TCP connect to the app port is attempted. Durations are in seconds.
+#[derive(Debug, Clone, Validate, Serialize, Deserialize)]
+pub struct HealthCheck {
+    #[validate(custom(function = "validate_path"))]
+    pub path: Option<String>,
+    #[validate(range(min = 100, max = 599))]
+    #[serde(default = "default_status")]
+    pub status: u16,
+    #[validate(range(min = 1, max = 300))]
+    #[serde(default = "default_interval")]
+    pub interval: u64,
+    #[validate(range(min = 1, max = 300))]
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+    #[validate(range(min = 1, max = 100))]
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+    #[validate(range(max = 3600))]
+    #[serde(default)]
+    pub start_period: u64,
+}
+
+fn default_status() -> u16 {
+    200
+}
+
+fn default_interval() -> u64 {
+    5
+}
+
+fn default_timeout() -> u64 {
+    3
+}
+
+fn default_retries() -> u32 {
+    5
+}
+
+fn validate_domains(domains: &[String]) -> Result<(), ValidationError> {
+    if !domains.iter().all(|d| is_valid_domain(d)) {
+        return Err(ValidationError::new("domains must be valid hostnames"));
+    }
+    Ok(())
+}
+
+fn validate_memory(memory: &str) -> Result<(), ValidationError> {
+    // Docker refuses anything below 6 MB
+    if parse_size(memory).is_none_or(|bytes| bytes < 6 * 1024 * 1024) {
+        return Err(ValidationError::new(
+            "memory must be a size of at least 6m, e.g. 512m or 1g",
+        ));
+    }
+    Ok(())
+}
+
+fn validate_restart(restart: &str) -> Result<(), ValidationError> {
+    if parse_restart_policy(restart).is_none() {
+        return Err(ValidationError::new(
+            "restart must be one of no, on-failure[:max], always or unless-stopped",
+        ));
+    }
+    Ok(())
+}
+
+/// Parse a restart setting the way `docker run --restart` takes it, e.g. `on-failure:5`.
+fn parse_restart_policy(restart: &str) -> Option<RestartPolicy> {
+    let (name, max) = match restart.split_once(':') {
+        Some((name, max)) => (name, Some(max.parse::<i64>().ok().filter(|max| *max > 0)?)),
+        None => (restart, None),
+    };
+    let name = match name {
+        "no" => RestartPolicyNameEnum::NO,
+        "on-failure" => RestartPolicyNameEnum::ON_FAILURE,
+        "always" => RestartPolicyNameEnum::ALWAYS,
+        "unless-stopped" => RestartPolicyNameEnum::UNLESS_STOPPED,
+        _ => return None,
+    };
+    // Only on-failure takes a retry count
+    if max.is_some() && name != RestartPolicyNameEnum::ON_FAILURE {
+        return None;
+    }
+    Some(RestartPolicy {
+        name: Some(name),
+        maximum_retry_count: max,
+    })
+}
+
+fn validate_cron(jobs: &BTreeMap<String, CronJob>) -> Result<(), ValidationError> {
+    if !jobs.keys().all(|name| is_valid_process_name(name)) {
+        return Err(ValidationError::new(
+            "cron job names must contain only lowercase letters, digits and underscores",
+        ));
+    }
+    if jobs.values().any(|job| Schedule::parse(&job.schedule).is_err()) {
+        return Err(ValidationError::new(
+            "cron schedules must have 5 fields, e.g. */15 * * * *, or be one of @hourly, @daily, @weekly, @monthly, @yearly",
+        ));
+    }
+    if jobs.values().any(|job| job.command.trim().is_empty()) {
+        return Err(ValidationError::new("cron commands must not be empty"));
+    }
+    Ok(())
+}
+
+fn validate_processes(processes: &BTreeMap<String, String>) -> Result<(), ValidationError> {
+    if !processes.keys().all(|name| is_valid_process_name(name)) {
+        return Err(ValidationError::new(
+            "process names must contain only lowercase letters, digits and underscores",
+        ));
+    }
+    if processes.contains_key(RELEASE) {
+        return Err(ValidationError::new(
+            "release is run once per deploy, set it as release rather than as a process",
+        ));
+    }
+    if processes.values().any(|command| command.trim().is_empty()) {
+        return Err(ValidationError::new("process commands must not be empty"));
+    }
+    Ok(())
 }
 
-fn validate_port(port: u16) -> Result<(), ValidationError> {
-    if !is_free(port) {
-        return Err(ValidationError::new("port is already in use"));
+fn validate_path(path: &str) -> Result<(), ValidationError> {
+    // The path ends up in a shell command, so keep it to plain URL characters
+    let valid = path.starts_with('/')
+        && path
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "/-._~?&=%".contains(c));
+    if !valid {
+        return Err(ValidationError::new(
+            "path must start with / and contain only URL characters",
+        ));
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(name: RestartPolicyNameEnum, max: Option<i64>) -> Option<RestartPolicy> {
+        Some(RestartPolicy {
+            name: Some(name),
+            maximum_retry_count: max,
+        })
+    }
+
+    #[test]
+    fn parse_restart_policy_reads_docker_policies() {
+        assert_eq!(parse_restart_policy("no"), policy(RestartPolicyNameEnum::NO, None));
+        assert_eq!(
+            parse_restart_policy("always"),
+            policy(RestartPolicyNameEnum::ALWAYS, None)
+        );
+        assert_eq!(
+            parse_restart_policy("unless-stopped"),
+            policy(RestartPolicyNameEnum::UNLESS_STOPPED, None)
+        );
+        assert_eq!(
+            parse_restart_policy("on-failure"),
+            policy(RestartPolicyNameEnum::ON_FAILURE, None)
+        );
+        assert_eq!(
+            parse_restart_policy("on-failure:5"),
+            policy(RestartPolicyNameEnum::ON_FAILURE, Some(5))
+        );
+    }
+
+    #[test]
+    fn parse_restart_policy_rejects_invalid_policies() {
+        assert_eq!(parse_restart_policy(""), None);
+        assert_eq!(parse_restart_policy("sometimes"), None);
+        assert_eq!(parse_restart_policy("Always"), None);
+        assert_eq!(parse_restart_policy("on-failure:0"), None);
+        assert_eq!(parse_restart_policy("on-failure:-1"), None);
+        assert_eq!(parse_restart_policy("on-failure:"), None);
+        assert_eq!(parse_restart_policy("always:3"), None);
+    }
+
+    #[test]
+    fn restart_policy_defaults_to_unless_stopped() {
+        let config = RukuConfig::default();
+        assert_eq!(
+            Some(config.restart_policy()),
+            policy(RestartPolicyNameEnum::UNLESS_STOPPED, None)
+        );
+    }
+
+    #[test]
+    fn validate_processes_rejects_a_release_process() {
+        let processes = BTreeMap::from([
+            ("web".to_string(), "npm start".to_string()),
+            (RELEASE.to_string(), "npm run migrate".to_string()),
+        ]);
+        assert!(validate_processes(&processes).is_err());
+        assert!(validate_processes(&BTreeMap::from([("web".to_string(), "npm start".to_string())])).is_ok());
+    }
+}
@@ -0,0 +1,113 @@
+use bollard::models::EndpointSettings;
+use bollard::network::{ConnectNetworkOptions, CreateNetworkOptions, DisconnectNetworkOptions, InspectNetworkOptions};
+use bollard::Docker;
+
+use crate::logger::Logger;
+
+/// Network of the proxy itself, it is connected to each app network on top of it.
+pub const NETWORK: &str = "ruku";
+
+/// The private network of an app, which the proxy and any services the app uses get attached to.
+pub fn app_network(app: &str) -> String {
+    format!("ruku-{}", app)
+}
+
+/// A user-defined Docker bridge network managed by ruku.
+pub struct Network<'a> {
+    log: &'a Logger,
+    docker: &'a Docker,
+    name: &'a str,
+}
+
+impl<'a> Network<'a> {
+    pub fn new(log: &'a Logger, docker: &'a Docker, name: &'a str) -> Network<'a> {
+        Network { log, docker, name }
+    }
+
+    /// Create the network unless it already exists.
+    pub async fn ensure(&self) {
+        if self
+            .docker
+            .inspect_network(self.name, None::<InspectNetworkOptions<String>>)
+            .await
+            .is_ok()
+        {
+            return;
+        }
+
+        let options = CreateNetworkOptions {
+            name: self.name,
+            driver: "bridge",
+            ..Default::default()
+        };
+        self.docker.create_network(options).await.unwrap_or_else(|e| {
+            self.log
+                .error(&format!("Failed to create network {}: {}", self.name, e));
+            std::process::exit(1);
+        });
+        self.log.step(&format!("Created network {}", self.name));
+    }
+
+    /// Attach a container to the network, doing nothing if it is already attached.
+    pub async fn connect(&self, container: &str) {
+        if self.containers().await.iter().any(|c| c == container) {
+            return;
+        }
+
+        let options = ConnectNetworkOptions {
+            container,
+            endpoint_config: EndpointSettings::default(),
+        };
+        self.docker
+            .connect_network(self.name, options)
+            .await
+            .unwrap_or_else(|e| {
+                self.log.error(&format!(
+                    "Failed to connect {} to network {}: {}",
+                    container, self.name, e
+                ));
+                std::process::exit(1);
+            });
+    }
+
+    /// Detach whatever is still attached, e.g. the proxy, and remove the network.
+    pub async fn remove(&self) {
+        for container in self.containers().await {
+            let options = DisconnectNetworkOptions {
+                container: container.as_str(),
+                force: true,
+            };
+            self.docker
+                .disconnect_network(self.name, options)
+                .await
+                .unwrap_or_else(|e| {
+                    self.log.error(&format!(
+                        "Failed to disconnect {} from network {}: {}",
+                        container, self.name, e
+                    ));
+                    std::process::exit(1);
+                });
+        }
+
+        if self.docker.remove_network(self.name).await.is_ok() {
+            self.log.step(&format!("Removed network {}", self.name));
+        }
+    }
+
+    /// Names of the containers attached to the network, empty if it doesn't exist.
+    async fn containers(&self) -> Vec<String> {
+        let Ok(network) = self
+            .docker
+            .inspect_network(self.name, None::<InspectNetworkOptions<String>>)
+            .await
+        else {
+            return vec![];
+        };
+        network
+            .containers
+            .unwrap_or_default()
+            .into_values()
+            .filter_map(|c| c.name)
+            .collect()
+    }
+}
@@ -0,0 +1,98 @@
+use std::fs;
+use std::path::PathBuf;
+
+use port_selector::{is_free, random_free_tcp_port};
+
+use crate::logger::Logger;
+use crate::server_config::ServerConfig;
+
+/// Host ports assigned to exposed apps, persisted per app in `<data_root>/<app>/PORT`.
+pub struct Ports<'a> {
+    log: &'a Logger,
+    data_root: &'a PathBuf,
+}
+
+impl<'a> Ports<'a> {
+    pub fn new(log: &'a Logger, server_config: &'a ServerConfig) -> Ports<'a> {
+        Ports {
+            log,
+            data_root: &server_config.data_root,
+        }
+    }
+
+    pub fn get(&self, app: &str) -> Option<u16> {
+        let content = fs::read_to_string(self.path(app)).ok()?;
+        content.trim().parse().ok()
+    }
+
+    /// The app, other than `app`, that has been assigned `port`.
+    pub fn owner(&self, port: u16, app: &str) -> Option<String> {
+        let entries = fs::read_dir(self.data_root).ok()?;
+        entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .find(|other| other != app && self.get(other) == Some(port))
+    }
+
+    /// Assign a host port to the app: `requested` if given, otherwise the one it already has, otherwise
+    /// a free one nobody else owns. `published` is the port the app is reachable on right now, which is
+    /// naturally in use.
+    pub fn assign(&self, app: &str, requested: Option<u16>, published: Option<u16>) -> u16 {
+        let port = match requested.or_else(|| self.get(app)) {
+            Some(port) => {
+                if let Some(owner) = self.owner(port, app) {
+                    self.log.error(&format!("Port {} is already used by {}", port, owner));
+                    std::process::exit(1);
+                }
+                if Some(port) != published && !is_free(port) {
+                    self.log.error(&format!("Port {} is already in use", port));
+                    std::process::exit(1);
+                }
+                port
+            }
+            None => self.allocate(app),
+        };
+
+        if self.get(app) != Some(port) {
+            let path = self.path(app);
+            fs::create_dir_all(path.parent().unwrap()).unwrap_or_else(|e| {
+                self.log.error(&format!("Error creating directory: {}", e));
+                std::process::exit(1);
+            });
+            fs::write(&path, port.to_string()).unwrap_or_else(|e| {
+                self.log.error(&format!("Error writing port file: {}", e));
+                std::process::exit(1);
+            });
+            self.log.step(&format!("Assigned host port {}", port));
+        }
+        port
+    }
+
+    /// Give up the app's host port, e.g. once it is no longer exposed.
+    pub fn release(&self, app: &str) {
+        let path = self.path(app);
+        if path.exists() {
+            fs::remove_file(&path).unwrap_or_else(|e| {
+                self.log.error(&format!("Error removing port file: {}", e));
+                std::process::exit(1);
+            });
+        }
+    }
+
+    fn allocate(&self, app: &str) -> u16 {
+        // A port can be free right now while belonging to an app that is stopped
+        for _ in 0..100 {
+            if let Some(port) = random_free_tcp_port() {
+                if self.owner(port, app).is_none() {
+                    return port;
+                }
+            }
+        }
+        self.log.error("Failed to find a free host port");
+        std::process::exit(1);
+    }
+
+    fn path(&self, app: &str) -> PathBuf {
+        self.data_root.join(app).join("PORT")
+    }
+}
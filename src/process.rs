@@ -0,0 +1,108 @@
+use std::collections::BTreeMap;
+
+use crate::model::RukuConfig;
+
+/// The process type that serves HTTP. It is the only one the proxy routes to and that gets published
+/// on the host.
+pub const WEB: &str = "web";
+
+/// The Procfile entry Heroku runs once per deploy rather than keeping up, which is ruku.yml's `release`.
+pub const RELEASE: &str = "release";
+
+/// A kind of process the app runs, each in its own container from the same image.
+pub struct Process {
+    pub name: String,
+    /// Command run with bash, the image's own start command when left out
+    pub command: Option<String>,
+}
+
+impl Process {
+    pub fn is_web(&self) -> bool {
+        self.name == WEB
+    }
+}
+
+/// The process types declared for the app, or a single `web` process running the image's start command
+/// when there are none.
+pub fn processes(config: &RukuConfig) -> Vec<Process> {
+    if config.processes.is_empty() {
+        return vec![Process {
+            name: WEB.to_string(),
+            command: None,
+        }];
+    }
+    config
+        .processes
+        .iter()
+        .map(|(name, command)| Process {
+            name: name.clone(),
+            command: Some(command.clone()),
+        })
+        .collect()
+}
+
+/// Parse a Procfile, one `<process>: <command>` per line. Blank lines and `#` comments are skipped.
+pub fn parse_procfile(content: &str) -> Result<BTreeMap<String, String>, String> {
+    let mut processes = BTreeMap::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((name, command)) = line.split_once(':') else {
+            return Err(format!(
+                "Invalid Procfile line {}: expected <process>: <command>",
+                number + 1
+            ));
+        };
+        processes.insert(name.trim().to_string(), command.trim().to_string());
+    }
+    Ok(processes)
+}
+
+/// Process names end up in container names, so keep them to lowercase letters, digits and underscores.
+pub fn is_valid_process_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 32
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_procfile_reads_processes() {
+        let procfile = "web: bundle exec puma -p $PORT\n\n# background jobs\nworker:  sidekiq -q default \n";
+        let processes = parse_procfile(procfile).unwrap();
+        assert_eq!(processes.len(), 2);
+        assert_eq!(processes["web"], "bundle exec puma -p $PORT");
+        assert_eq!(processes["worker"], "sidekiq -q default");
+    }
+
+    #[test]
+    fn parse_procfile_keeps_colons_in_commands() {
+        let processes = parse_procfile("web: gunicorn app:main --bind 0.0.0.0:8000").unwrap();
+        assert_eq!(processes["web"], "gunicorn app:main --bind 0.0.0.0:8000");
+    }
+
+    #[test]
+    fn parse_procfile_rejects_lines_without_a_process_name() {
+        assert_eq!(
+            parse_procfile("web: npm start\nnpm run worker"),
+            Err("Invalid Procfile line 2: expected <process>: <command>".to_string())
+        );
+    }
+
+    #[test]
+    fn is_valid_process_name_matches_container_names() {
+        assert!(is_valid_process_name("web"));
+        assert!(is_valid_process_name("queue_2"));
+        assert!(!is_valid_process_name(""));
+        assert!(!is_valid_process_name("Web"));
+        assert!(!is_valid_process_name("my-worker"));
+        assert!(!is_valid_process_name(&"a".repeat(33)));
+    }
+}
@@ -0,0 +1,297 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use bollard::exec::{CreateExecOptions, StartExecResults};
+use bollard::models::{HostConfig, PortBinding, PortMap, RestartPolicy, RestartPolicyNameEnum};
+use bollard::Docker;
+use futures_util::StreamExt;
+
+use crate::logger::Logger;
+use crate::managed::{Ensured, ManagedContainer};
+use crate::network::{app_network, Network, NETWORK};
+use crate::server_config::{AcmeConfig, ServerConfig};
+
+const PROXY_NAME: &str = "ruku-proxy";
+const CA_ROOT_FILE: &str = "acme_ca_root.pem";
+
+/// Where an app's domains should be routed to.
+pub struct Route {
+    pub app: String,
+    pub domains: Vec<String>,
+    pub port: u16,
+}
+
+/// The Caddy reverse proxy that routes requests to apps by their `Host` header. Caddy also obtains
+/// and renews the TLS certificates of app domains through ACME, keeping them under `ruku_root`.
+pub struct Proxy<'a> {
+    log: &'a Logger,
+    docker: &'a Docker,
+    server_config: &'a ServerConfig,
+    container: ManagedContainer<'a>,
+    dir: PathBuf,
+    certs_dir: PathBuf,
+}
+
+impl<'a> Proxy<'a> {
+    pub fn new(log: &'a Logger, docker: &'a Docker, server_config: &'a ServerConfig) -> Proxy<'a> {
+        Proxy {
+            log,
+            docker,
+            server_config,
+            container: ManagedContainer::new(log, docker, PROXY_NAME.to_string(), "proxy"),
+            dir: server_config.ruku_root.join("proxy"),
+            // Kept apart from `data_root`, whose entries are named after apps
+            certs_dir: server_config.ruku_root.join("certs"),
+        }
+    }
+
+    /// Write the proxy configuration for `routes` and make sure the proxy is running with it.
+    pub async fn sync(&self, routes: &[Route]) {
+        // Certificates used to be kept in `data_root`, move them rather than have them all issued again
+        let old_certs_dir = self.server_config.data_root.join(".certs");
+        if old_certs_dir.exists() && !self.certs_dir.exists() {
+            fs::rename(&old_certs_dir, &self.certs_dir).unwrap_or_else(|e| {
+                self.log.error(&format!("Error moving certificates: {}", e));
+                std::process::exit(1);
+            });
+        }
+        for dir in [&self.dir, &self.certs_dir] {
+            fs::create_dir_all(dir).unwrap_or_else(|e| {
+                self.log.error(&format!("Error creating directory: {}", e));
+                std::process::exit(1);
+            });
+        }
+        // The proxy only sees its own config directory, so bring the ACME root certificate in there
+        let acme = &self.server_config.acme;
+        if let Some(ca_root) = &acme.ca_root {
+            fs::copy(ca_root, self.dir.join(CA_ROOT_FILE)).unwrap_or_else(|e| {
+                self.log.error(&format!(
+                    "Error copying ACME root certificate {}: {}",
+                    ca_root.display(),
+                    e
+                ));
+                std::process::exit(1);
+            });
+        }
+        fs::write(self.dir.join("Caddyfile"), caddyfile(routes, acme)).unwrap_or_else(|e| {
+            self.log.error(&format!("Error writing proxy config: {}", e));
+            std::process::exit(1);
+        });
+
+        Network::new(self.log, self.docker, NETWORK).ensure().await;
+
+        // Don't claim ports 80 and 443 on servers that have no use for the proxy
+        if routes.iter().all(|r| r.domains.is_empty()) && self.container.get().await.is_none() {
+            return;
+        }
+        if let Ensured::Running = self.container.ensure(&self.spec(), self.config()).await {
+            self.reload().await;
+        }
+
+        // The proxy reaches each app on the app's own network
+        for route in routes.iter().filter(|r| !r.domains.is_empty()) {
+            let network = app_network(&route.app);
+            let network = Network::new(self.log, self.docker, &network);
+            network.ensure().await;
+            network.connect(PROXY_NAME).await;
+        }
+        self.log.step("Proxy configuration updated");
+    }
+
+    /// Everything the proxy container is created from, other settings only affect the Caddyfile.
+    fn spec(&self) -> String {
+        format!(
+            "{}|{}|{}",
+            self.server_config.proxy_image,
+            self.dir.display(),
+            self.certs_dir.display()
+        )
+    }
+
+    fn config(&self) -> bollard::container::Config<String> {
+        let mut port_bindings = PortMap::new();
+        let mut exposed_ports: HashMap<String, HashMap<(), ()>> = HashMap::new();
+        for port in ["80", "443"] {
+            let exposed_port = format!("{}/tcp", port);
+            port_bindings.insert(
+                exposed_port.clone(),
+                Some(vec![PortBinding {
+                    host_ip: None,
+                    host_port: Some(port.to_string()),
+                }]),
+            );
+            exposed_ports.insert(exposed_port, HashMap::new());
+        }
+
+        let host_config = HostConfig {
+            port_bindings: Some(port_bindings),
+            binds: Some(vec![
+                format!("{}:/etc/caddy", self.dir.display()),
+                // Caddy keeps its certificates and ACME account under /data
+                format!("{}:/data", self.certs_dir.display()),
+            ]),
+            network_mode: Some(NETWORK.to_string()),
+            restart_policy: Some(RestartPolicy {
+                name: Some(RestartPolicyNameEnum::UNLESS_STOPPED),
+                maximum_retry_count: None,
+            }),
+            ..Default::default()
+        };
+
+        bollard::container::Config {
+            image: Some(self.server_config.proxy_image.clone()),
+            host_config: Some(host_config),
+            exposed_ports: Some(exposed_ports),
+            ..Default::default()
+        }
+    }
+
+    /// Have the running proxy pick up the new configuration. Caddy keeps serving the old one if the
+    /// new configuration is invalid.
+    async fn reload(&self) {
+        let options = CreateExecOptions {
+            cmd: Some(vec!["caddy", "reload", "--config", "/etc/caddy/Caddyfile"]),
+            attach_stdout: Some(true),
+            attach_stderr: Some(true),
+            ..Default::default()
+        };
+        let exec = self.docker.create_exec(PROXY_NAME, options).await.unwrap_or_else(|e| {
+            self.log.error(&format!("Failed to reload proxy: {}", e));
+            std::process::exit(1);
+        });
+
+        let mut output = String::new();
+        if let Ok(StartExecResults::Attached { output: mut stream, .. }) = self.docker.start_exec(&exec.id, None).await
+        {
+            while let Some(Ok(chunk)) = stream.next().await {
+                output.push_str(&chunk.to_string());
+            }
+        }
+
+        let exit_code = self.docker.inspect_exec(&exec.id).await.ok().and_then(|e| e.exit_code);
+        if exit_code != Some(0) {
+            self.log.error(&format!("Failed to reload proxy: {}", output.trim()));
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Render the Caddyfile for `routes`. Upstreams are looked up through Docker's DNS on every refresh,
+/// so containers coming and going during a deploy are picked up without a reload.
+fn caddyfile(routes: &[Route], acme: &AcmeConfig) -> String {
+    let mut global = vec![];
+    if acme.enabled {
+        if let Some(email) = &acme.email {
+            global.push(format!("email {}", email));
+        }
+        global.push(format!("acme_ca {}", acme.directory));
+        if acme.ca_root.is_some() {
+            global.push(format!("acme_ca_root /etc/caddy/{}", CA_ROOT_FILE));
+        }
+        if let Some(dns) = &acme.dns {
+            global.push(format!("acme_dns {}", dns));
+        }
+    } else {
+        global.push("auto_https off".to_string());
+    }
+
+    let mut config = format!(
+        "{{\n{}\n}}\n",
+        global
+            .iter()
+            .map(|l| format!("    {}", l))
+            .collect::<Vec<_>>()
+            .join("\n")
+    );
+    for route in routes.iter().filter(|r| !r.domains.is_empty()) {
+        // A bare domain makes Caddy get a certificate for it and redirect HTTP to HTTPS
+        let scheme = if acme.enabled { "" } else { "http://" };
+        let addresses: Vec<String> = route.domains.iter().map(|d| format!("{}{}", scheme, d)).collect();
+        config.push_str(&format!(
+            r#"
+# {app}
+{addresses} {{
+    reverse_proxy {{
+        dynamic a {{
+            name {app}
+            port {port}
+            refresh 1s
+        }}
+        lb_try_duration 5s
+    }}
+}}
+"#,
+            app = route.app,
+            addresses = addresses.join(", "),
+            port = route.port
+        ));
+    }
+    config
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn routes() -> Vec<Route> {
+        vec![
+            Route {
+                app: "blog".to_string(),
+                domains: vec!["blog.example.com".to_string(), "www.blog.example.com".to_string()],
+                port: 3000,
+            },
+            Route {
+                app: "worker".to_string(),
+                domains: vec![],
+                port: 8080,
+            },
+        ]
+    }
+
+    #[test]
+    fn caddyfile_routes_domains_to_the_app_alias() {
+        let config = caddyfile(&routes(), &AcmeConfig::default());
+        assert!(config.contains("blog.example.com, www.blog.example.com {"));
+        assert!(config.contains("name blog\n"));
+        assert!(config.contains("port 3000\n"));
+        // Apps without domains get no site block
+        assert!(!config.contains("worker"));
+        assert!(!config.contains("8080"));
+    }
+
+    #[test]
+    fn caddyfile_sets_acme_options() {
+        let acme = AcmeConfig {
+            email: Some("ops@example.com".to_string()),
+            directory: "https://pebble:14000/dir".to_string(),
+            ca_root: Some(PathBuf::from("/etc/pebble.pem")),
+            dns: Some("cloudflare token".to_string()),
+            ..Default::default()
+        };
+        let config = caddyfile(&routes(), &acme);
+        assert!(config.starts_with("{\n"));
+        assert!(config.contains("    email ops@example.com\n"));
+        assert!(config.contains("    acme_ca https://pebble:14000/dir\n"));
+        assert!(config.contains(&format!("    acme_ca_root /etc/caddy/{}\n", CA_ROOT_FILE)));
+        assert!(config.contains("    acme_dns cloudflare token\n"));
+        assert!(!config.contains("auto_https off"));
+        assert!(!config.contains("http://"));
+    }
+
+    #[test]
+    fn caddyfile_serves_plain_http_without_acme() {
+        let acme = AcmeConfig {
+            enabled: false,
+            email: Some("ops@example.com".to_string()),
+            ..Default::default()
+        };
+        let config = caddyfile(&routes(), &acme);
+        assert!(config.contains("    auto_https off\n"));
+        assert!(config.contains("http://blog.example.com, http://www.blog.example.com {"));
+        assert!(!config.contains("email"));
+        assert!(!config.contains("acme_ca"));
+    }
+}
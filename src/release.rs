@@ -0,0 +1,139 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::{env, fs};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::logger::Logger;
+use crate::model::RukuConfig;
+use crate::server_config::ServerConfig;
+
+/// A successful deploy, recorded so it can be listed and rolled back to later.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Release {
+    pub number: u32,
+    pub commit: String,
+    pub image: String,
+    pub env: BTreeMap<String, String>,
+    /// The ruku.yml settings, with the Procfile's processes, the release runs with. Missing for releases
+    /// recorded before they were kept.
+    #[serde(default)]
+    pub config: Option<RukuConfig>,
+    pub created_at: DateTime<Utc>,
+    pub pusher: String,
+    /// Set when this release was created by rolling back to an earlier one
+    #[serde(default)]
+    pub rollback_of: Option<u32>,
+}
+
+/// The release history of an app, persisted in `<data_root>/<app>/releases.yml`.
+pub struct Releases<'a> {
+    log: &'a Logger,
+    path: PathBuf,
+    releases: Vec<Release>,
+}
+
+impl<'a> Releases<'a> {
+    pub fn load(log: &'a Logger, server_config: &ServerConfig, app: &str) -> Releases<'a> {
+        let path = server_config.data_root.join(app).join("releases.yml");
+
+        let mut releases = vec![];
+        if path.exists() {
+            let content = fs::read_to_string(&path).unwrap_or_else(|e| {
+                log.error(&format!("Error reading releases file: {}", e));
+                std::process::exit(1);
+            });
+            releases = serde_yaml::from_str(&content).unwrap_or_else(|e| {
+                log.error(&format!("Error parsing releases file: {}", e));
+                std::process::exit(1);
+            });
+        }
+
+        Releases { log, path, releases }
+    }
+
+    pub fn all(&self) -> &[Release] {
+        &self.releases
+    }
+
+    /// The release that is currently deployed.
+    pub fn current(&self) -> Option<&Release> {
+        self.releases.last()
+    }
+
+    pub fn get(&self, number: u32) -> Option<&Release> {
+        self.releases.iter().find(|r| r.number == number)
+    }
+
+    /// The release before the current one, i.e. the default rollback target.
+    pub fn previous(&self) -> Option<&Release> {
+        self.releases.iter().rev().nth(1)
+    }
+
+    /// Images of the last `count` releases, newest first.
+    pub fn recent_images(&self, count: usize) -> Vec<String> {
+        let mut images: Vec<String> = vec![];
+        for release in self.releases.iter().rev() {
+            if images.len() == count {
+                break;
+            }
+            if !images.contains(&release.image) {
+                images.push(release.image.clone());
+            }
+        }
+        images
+    }
+
+    pub fn next_number(&self) -> u32 {
+        self.current().map_or(1, |r| r.number + 1)
+    }
+
+    /// Record a new release and save the history.
+    pub fn add(
+        &mut self,
+        commit: &str,
+        image: &str,
+        env: &BTreeMap<String, String>,
+        config: &RukuConfig,
+        rollback_of: Option<u32>,
+    ) -> &Release {
+        let release = Release {
+            number: self.next_number(),
+            commit: commit.to_string(),
+            image: image.to_string(),
+            env: env.clone(),
+            config: Some(config.clone()),
+            created_at: Utc::now(),
+            pusher: pusher(),
+            rollback_of,
+        };
+        self.releases.push(release);
+        self.save();
+        self.releases.last().unwrap()
+    }
+
+    fn save(&self) {
+        fs::create_dir_all(self.path.parent().unwrap()).unwrap_or_else(|e| {
+            self.log.error(&format!("Error creating directory: {}", e));
+            std::process::exit(1);
+        });
+
+        let content = serde_yaml::to_string(&self.releases).unwrap_or_else(|e| {
+            self.log.error(&format!("Error serializing releases: {}", e));
+            std::process::exit(1);
+        });
+        fs::write(&self.path, content).unwrap_or_else(|e| {
+            self.log.error(&format!("Error writing releases file: {}", e));
+            std::process::exit(1);
+        });
+    }
+}
+
+/// Who triggered the release. Every push comes in as the same system user, so `RUKU_USER` can be set
+/// per key in `authorized_keys` to tell pushers apart.
+pub fn pusher() -> String {
+    env::var("RUKU_USER")
+        .or_else(|_| env::var("USER"))
+        .unwrap_or_else(|_| "unknown".to_string())
+}
@@ -0,0 +1,48 @@
+use std::collections::BTreeMap;
+
+use crate::line_file::LineFile;
+use crate::logger::Logger;
+use crate::server_config::ServerConfig;
+
+/// How many containers to run per process type, set with `ps:scale` and persisted as `<process>=<count>`
+/// lines in `<data_root>/<app>/SCALE`. Process types that aren't listed run a single container.
+pub struct Scale<'a> {
+    file: LineFile<'a>,
+    counts: BTreeMap<String, usize>,
+}
+
+impl<'a> Scale<'a> {
+    pub fn load(log: &'a Logger, server_config: &ServerConfig, app: &str) -> Scale<'a> {
+        let file = LineFile::new(log, server_config, app, "SCALE", "scale");
+        let counts = file
+            .read()
+            .iter()
+            .filter_map(|line| parse_count(line))
+            .map(|(process, count)| (process.to_string(), count))
+            .collect();
+
+        Scale { file, counts }
+    }
+
+    pub fn get(&self, process: &str) -> usize {
+        self.counts.get(process).copied().unwrap_or(1)
+    }
+
+    pub fn set(&mut self, process: &str, count: usize) {
+        self.counts.insert(process.to_string(), count);
+    }
+
+    pub fn save(&self) {
+        self.file.write(
+            self.counts
+                .iter()
+                .map(|(process, count)| format!("{}={}", process, count)),
+        );
+    }
+}
+
+/// Split a `<process>=<count>` pair, e.g. `web=3`.
+pub fn parse_count(pair: &str) -> Option<(&str, usize)> {
+    let (process, count) = pair.trim().split_once('=')?;
+    Some((process, count.parse().ok()?))
+}
@@ -1,11 +1,68 @@
+use std::fs;
 use std::path::PathBuf;
 
+use serde::Deserialize;
+
 pub struct ServerConfig {
     pub ruku_root: PathBuf,
     pub ruku_binary: PathBuf,
     pub data_root: PathBuf,
     pub git_root: PathBuf,
     pub apps_root: PathBuf,
+    pub keep_releases: usize,
+    pub proxy_image: String,
+    pub forwarder_image: String,
+    pub acme: AcmeConfig,
+}
+
+/// How the proxy gets TLS certificates for app domains.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct AcmeConfig {
+    /// Serve app domains over HTTPS, otherwise plain HTTP only
+    pub enabled: bool,
+    /// Contact address registered with the ACME account
+    pub email: Option<String>,
+    /// ACME directory URL, e.g. a local Pebble instance for testing
+    pub directory: String,
+    /// Root certificate to trust for the ACME directory, needed for Pebble
+    pub ca_root: Option<PathBuf>,
+    /// Caddy `acme_dns` provider config to solve DNS-01 challenges, e.g. `cloudflare <token>`.
+    /// The provider module must be built into `proxy_image`.
+    pub dns: Option<String>,
+}
+
+impl Default for AcmeConfig {
+    fn default() -> Self {
+        AcmeConfig {
+            enabled: true,
+            email: None,
+            directory: "https://acme-v02.api.letsencrypt.org/directory".to_string(),
+            ca_root: None,
+            dns: None,
+        }
+    }
+}
+
+/// Optional server wide settings read from `<ruku_root>/config.yml`.
+#[derive(Deserialize)]
+#[serde(default)]
+struct Settings {
+    keep_releases: usize,
+    proxy_image: String,
+    forwarder_image: String,
+    acme: AcmeConfig,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            keep_releases: 5,
+            proxy_image: "caddy:2".to_string(),
+            forwarder_image: "alpine/socat".to_string(),
+            acme: AcmeConfig::default(),
+        }
+    }
 }
 
 impl ServerConfig {
@@ -13,12 +70,23 @@ impl ServerConfig {
         let home_dir = home::home_dir().ok_or("Could not determine home directory")?;
         let ruku_root = home_dir.join(".ruku");
 
+        let settings_path = ruku_root.join("config.yml");
+        let settings: Settings = if settings_path.exists() {
+            serde_yaml::from_str(&fs::read_to_string(&settings_path)?)?
+        } else {
+            Settings::default()
+        };
+
         Ok(ServerConfig {
             ruku_root: home_dir.join(".ruku"),
             ruku_binary: PathBuf::from("/usr/bin/ruku"),
             data_root: ruku_root.join("data"),
             git_root: ruku_root.join("repos"),
             apps_root: home_dir.join("apps"),
+            keep_releases: settings.keep_releases.max(1),
+            proxy_image: settings.proxy_image,
+            forwarder_image: settings.forwarder_image,
+            acme: settings.acme,
         })
     }
 }
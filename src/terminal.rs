@@ -0,0 +1,30 @@
+use cmd_lib::{run_cmd, run_fun};
+
+/// Puts the terminal ruku was started from in raw mode, so keystrokes like Ctrl-C reach a TTY in a
+/// container rather than ruku itself. The previous settings are restored on drop.
+pub struct RawMode {
+    settings: String,
+}
+
+impl RawMode {
+    /// Nothing happens when stdin isn't a terminal, e.g. when input is piped.
+    pub fn enable() -> Option<RawMode> {
+        let settings = run_fun!(stty "-g").ok()?;
+        run_cmd!(stty raw -echo).ok()?;
+        Some(RawMode { settings })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let settings = &self.settings;
+        let _ = run_cmd!(stty $settings);
+    }
+}
+
+/// Rows and columns of the terminal on stdin.
+pub fn size() -> Option<(u16, u16)> {
+    let size = run_fun!(stty size).ok()?;
+    let (rows, columns) = size.trim().split_once(' ')?;
+    Some((rows.parse().ok()?, columns.parse().ok()?))
+}